@@ -0,0 +1,225 @@
+//! Reading a tarball in memory, so a third-party release can be crawled
+//! without extracting it. Gzip is undone by the system `gzip`, like
+//! rendering goes through Graphviz `dot`.
+
+use crate::error::Result;
+use crate::{
+    CrawlError, CrawlOptions, Include, IncludeMapping, ParseOptions, normalize_lexically,
+    parse_exts,
+};
+use log::debug;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const BLOCK: usize = 512;
+
+/// One regular file in the archive.
+struct Entry<'a> {
+    /// Path inside the archive, without any leading `./`
+    path: PathBuf,
+    mtime: SystemTime,
+    data: &'a [u8],
+}
+
+/// Build the include mapping of a `.tar`, `.tar.gz` or `.tgz` file. Nodes are
+/// in-archive paths; quoted includes resolve against the including entry's
+/// directory, then each include dir taken as an in-archive path, e.g.
+/// `-I libfoo-1.0/include`; with a quote base, quoted includes start from
+/// the top of the archive instead. Walk options other than exts and ignores
+/// do not apply.
+pub fn map_archive<E: From<CrawlError>>(
+    archive: &Path,
+    opts: &CrawlOptions,
+    on_file: impl Fn(&Path, &IncludeMapping) -> Result<(), E>,
+) -> Result<IncludeMapping, E> {
+    let bytes = read_archive(archive)?;
+    let entries = tar_entries(&bytes).map_err(|reason| CrawlError::Archive {
+        path: archive.to_path_buf(),
+        reason,
+    })?;
+    let known = entries
+        .iter()
+        .map(|e| e.path.clone())
+        .collect::<HashSet<_>>();
+    let exts = parse_exts(opts.walk.exts.as_deref());
+
+    let mut mapping = IncludeMapping::new();
+    for entry in &entries {
+        let relevant = entry
+            .path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| exts.contains(&e.to_ascii_lowercase()));
+        let ignored = opts
+            .walk
+            .ignores
+            .iter()
+            .any(|pat| crate::ignore_matches(&entry.path, pat));
+        if !relevant || ignored {
+            continue;
+        }
+
+        let size = entry.data.len() as u64;
+        let mut parsed = if opts.parse.max_file_size.is_some_and(|max| size > max) {
+            debug!(
+                "skipping {}: {} bytes is over --max-file-size",
+                entry.path.display(),
+                size
+            );
+            crate::ParsedFile::default()
+        } else {
+            crate::parse_reader(entry.data, &entry.path, &opts.parse)?
+        };
+        parsed.meta.size = size;
+        parsed.meta.mtime = Some(entry.mtime);
+
+        let mut own = IncludeMapping::new();
+        crate::record_includes_with(&entry.path, &parsed, &mut own, &opts.parse, |include| {
+            resolve(&entry.path, include, &opts.parse, &known)
+        });
+        on_file(&entry.path, &own)?;
+        mapping.merge(own);
+    }
+    Ok(mapping)
+}
+
+/// The archive's bytes, run through `gzip -dc` when compressed.
+fn read_archive(path: &Path) -> Result<Vec<u8>> {
+    let name = path.to_string_lossy();
+    if !(name.ends_with(".gz") || name.ends_with(".tgz")) {
+        return fs::read(path).map_err(|source| CrawlError::Io {
+            path: path.to_path_buf(),
+            source,
+        });
+    }
+    let failed = |reason: String| CrawlError::Archive {
+        path: path.to_path_buf(),
+        reason,
+    };
+    let out = Command::new("gzip")
+        .arg("-dc")
+        .arg(path)
+        .output()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                failed("`gzip` not found on PATH; it is needed to decompress".to_string())
+            }
+            _ => failed(format!("failed to run gzip: {}", e)),
+        })?;
+    if !out.status.success() {
+        return Err(failed(format!(
+            "gzip failed: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        )));
+    }
+    Ok(out.stdout)
+}
+
+/// Like the compiler, but looking among the archive's files instead of on disk.
+fn resolve(
+    including: &Path,
+    include: &Include,
+    opts: &ParseOptions,
+    known: &HashSet<PathBuf>,
+) -> Option<PathBuf> {
+    let local = match (include.system, &opts.quote_base) {
+        (true, _) => None,
+        (false, Some(_)) => Some(Path::new("")),
+        (false, None) => Some(including.parent().unwrap_or(Path::new(""))),
+    };
+    local
+        .into_iter()
+        .chain(opts.include_dirs.iter().map(PathBuf::as_path))
+        .map(|dir| normalize_lexically(&dir.join(&include.path)))
+        .find(|p| known.contains(p))
+}
+
+/// Regular files of a ustar/GNU/pax tar stream. Long names from GNU `L`
+/// records and pax `path=` headers are honored; links and directories
+/// are skipped.
+fn tar_entries(bytes: &[u8]) -> Result<Vec<Entry<'_>>, String> {
+    let mut entries = Vec::new();
+    let mut long_name: Option<String> = None;
+    let mut pos = 0;
+    while pos + BLOCK <= bytes.len() {
+        let header = &bytes[pos..pos + BLOCK];
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        let size = usize::try_from(octal(&header[124..136])?)
+            .map_err(|_| "entry too large for this platform".to_string())?;
+        let data_start = pos + BLOCK;
+        // Checked so a bogus huge size can't overflow; once the data is in
+        // bounds, the padded end below can't either
+        let data = data_start
+            .checked_add(size)
+            .and_then(|end| bytes.get(data_start..end))
+            .ok_or_else(|| format!("truncated entry at byte {}", pos))?;
+        pos = data_start + size.div_ceil(BLOCK) * BLOCK;
+
+        match header[156] {
+            b'L' => long_name = Some(text(data)),
+            b'x' => {
+                if let Some(path) = pax_path(data) {
+                    long_name = Some(path);
+                }
+            }
+            b'0' | 0 => {
+                let name = long_name.take().unwrap_or_else(|| {
+                    let name = text(&header[0..100]);
+                    let prefix = text(&header[345..500]);
+                    match &header[257..262] == b"ustar" && !prefix.is_empty() {
+                        true => format!("{}/{}", prefix, name),
+                        false => name,
+                    }
+                });
+                let mtime = UNIX_EPOCH + Duration::from_secs(octal(&header[136..148])?);
+                entries.push(Entry {
+                    path: normalize_lexically(Path::new(&name)),
+                    mtime,
+                    data,
+                });
+            }
+            _ => long_name = None,
+        }
+    }
+    Ok(entries)
+}
+
+/// A NUL-terminated header field.
+fn text(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// A numeric header field: octal digits, or GNU base-256 when the high bit is set.
+fn octal(field: &[u8]) -> Result<u64, String> {
+    if field.first().is_some_and(|b| b & 0x80 != 0) {
+        let mut n: u64 = u64::from(field[0] & 0x7f);
+        for &b in &field[1..] {
+            n = n
+                .checked_mul(256)
+                .and_then(|n| n.checked_add(u64::from(b)))
+                .ok_or_else(|| "number field overflows".to_string())?;
+        }
+        return Ok(n);
+    }
+    let digits = text(field);
+    let digits = digits.trim_matches(|c: char| c == ' ' || c == '\0');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(digits, 8).map_err(|_| format!("bad number field {:?}", digits))
+}
+
+/// The `path` record of a pax extended header: lines of `LEN key=value\n`.
+fn pax_path(data: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(data);
+    text.lines().find_map(|line| {
+        let (_, record) = line.split_once(' ')?;
+        record.strip_prefix("path=").map(str::to_string)
+    })
+}
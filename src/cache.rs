@@ -0,0 +1,94 @@
+//! On-disk cache of parsed includes, keyed by path and invalidated when a
+//! file's mtime or size changes.
+
+use crate::ParsedFile;
+use crate::error::{CrawlError, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Default cache file, relative to the current directory.
+pub const CACHE_FILE: &str = ".crawl-cache.json";
+
+/// Bumped whenever the on-disk layout or parse rules change, so stale
+/// caches are discarded instead of misread.
+const VERSION: u32 = 6;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Cache {
+    version: u32,
+    /// [`crate::ParseOptions::cache_key`] of the run that wrote the cache
+    key: String,
+    files: HashMap<PathBuf, Entry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    mtime: SystemTime,
+    size: u64,
+    parsed: ParsedFile,
+}
+
+/// mtime and size of `path`, or `None` if it cannot be stat'ed.
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let meta = fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}
+
+impl Cache {
+    /// Load the cache at `path` for parse options identified by `key`. A
+    /// missing, unreadable or outdated cache, or one written with other
+    /// options, yields an empty one; only a corrupt file is worth a warning.
+    pub fn load(path: &Path, key: &str) -> Cache {
+        let empty = || Cache {
+            version: VERSION,
+            key: key.to_string(),
+            files: HashMap::new(),
+        };
+        let Ok(text) = fs::read_to_string(path) else {
+            return empty();
+        };
+        match serde_json::from_str::<Cache>(&text) {
+            Ok(cache) if cache.version == VERSION && cache.key == key => cache,
+            Ok(_) => empty(),
+            Err(e) => {
+                warn!("ignoring corrupt cache {}: {}", path.display(), e);
+                empty()
+            }
+        }
+    }
+
+    /// Cached includes of `path`, if its mtime and size still match.
+    pub fn lookup(&self, path: &Path) -> Option<&ParsedFile> {
+        let entry = self.files.get(path)?;
+        let (mtime, size) = stamp(path)?;
+        (entry.mtime == mtime && entry.size == size).then_some(&entry.parsed)
+    }
+
+    /// Remember freshly parsed includes for `path` under its current stamp.
+    pub fn update(&mut self, path: PathBuf, parsed: ParsedFile) {
+        if let Some((mtime, size)) = stamp(&path) {
+            self.files.insert(
+                path,
+                Entry {
+                    mtime,
+                    size,
+                    parsed,
+                },
+            );
+        }
+    }
+
+    /// Write the cache to `path`, dropping entries for files that are gone.
+    pub fn save(mut self, path: &Path) -> Result<()> {
+        self.files.retain(|file, _| file.exists());
+        let json = serde_json::to_string(&self).map_err(CrawlError::Serialize)?;
+        fs::write(path, json).map_err(|source| CrawlError::Write {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+}
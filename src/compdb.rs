@@ -0,0 +1,115 @@
+//! Per-file include search paths read from a clang compilation database
+//! (`compile_commands.json`), as emitted by CMake, Bear or Meson.
+
+use crate::error::{CrawlError, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// One translation unit. Either `arguments` or `command` is present;
+/// `arguments` wins when both are.
+#[derive(Debug, Deserialize)]
+struct Entry {
+    directory: PathBuf,
+    file: PathBuf,
+    arguments: Option<Vec<String>>,
+    command: Option<String>,
+}
+
+/// Flags that add an include search directory.
+const DIR_FLAGS: &[&str] = &["-I", "-iquote", "-isystem", "-idirafter"];
+
+/// Load `path` and return each listed file's include directories, in
+/// command-line order. File and directory paths are canonical; relative
+/// ones are taken from the entry's `directory`.
+pub fn load(path: &Path) -> Result<HashMap<PathBuf, Vec<PathBuf>>> {
+    let invalid = |reason: String| CrawlError::Parse {
+        what: "compilation database",
+        path: Some(path.to_path_buf()),
+        reason,
+    };
+    let text = fs::read_to_string(path).map_err(|source| CrawlError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let entries: Vec<Entry> = serde_json::from_str(&text).map_err(|e| invalid(e.to_string()))?;
+
+    let mut dirs = HashMap::new();
+    for entry in entries {
+        let args = match (entry.arguments, entry.command) {
+            (Some(args), _) => args,
+            (None, Some(command)) => split_command(&command),
+            (None, None) => {
+                return Err(invalid(format!(
+                    "entry for {} has neither `arguments` nor `command`",
+                    entry.file.display()
+                )));
+            }
+        };
+        let file = crate::canonicalize_lenient(&entry.directory.join(&entry.file));
+        let found = include_dirs(&args)
+            .map(|d| crate::canonicalize_lenient(&entry.directory.join(d)))
+            .collect();
+        dirs.insert(file, found);
+    }
+    Ok(dirs)
+}
+
+/// The directory operands of `DIR_FLAGS`, given either as `-Idir` or `-I dir`.
+fn include_dirs(args: &[String]) -> impl Iterator<Item = &str> {
+    let mut args = args.iter();
+    std::iter::from_fn(move || {
+        while let Some(arg) = args.next() {
+            for flag in DIR_FLAGS {
+                match arg.strip_prefix(flag) {
+                    Some("") => return args.next().map(String::as_str),
+                    Some(dir) => return Some(dir),
+                    None => {}
+                }
+            }
+        }
+        None
+    })
+}
+
+/// Split a `command` string into arguments the way a POSIX shell would for
+/// the simple quoting build tools emit: single quotes, double quotes and
+/// backslash escapes.
+fn split_command(command: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut cur = String::new();
+    let mut in_arg = false;
+    let mut quote: Option<char> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"') | None, '\\') => {
+                if let Some(next) = chars.next() {
+                    cur.push(next);
+                }
+                in_arg = true;
+            }
+            (Some(_), c) => cur.push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                in_arg = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut cur));
+                    in_arg = false;
+                }
+            }
+            (None, c) => {
+                cur.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if in_arg {
+        args.push(cur);
+    }
+    args
+}
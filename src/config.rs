@@ -0,0 +1,56 @@
+//! Project defaults read from a `.crawlrc` TOML file, so a team shares
+//! one set of extensions and ignores without repeating flags.
+
+use crate::error::{CrawlError, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the config file looked up from the current directory upwards.
+pub const CONFIG_FILE: &str = ".crawlrc";
+
+/// Settings a `.crawlrc` may provide; unset keys leave the built-in defaults.
+///
+/// ```toml
+/// exts = ["c", "h", "def", "ipp"]
+/// ignores = ["build/", "third_party/"]
+/// follow_symlinks = true
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub exts: Option<Vec<String>>,
+    pub ignores: Option<Vec<String>>,
+    pub follow_symlinks: Option<bool>,
+}
+
+impl Config {
+    /// Load the `.crawlrc` in `start` or its nearest ancestor that has one.
+    /// Returns the default config if there is none.
+    pub fn discover(start: &Path) -> Result<Config> {
+        match find_config(start) {
+            Some(path) => Config::load(&path),
+            None => Ok(Config::default()),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Config> {
+        let text = fs::read_to_string(path).map_err(|source| CrawlError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        toml::from_str(&text).map_err(|e| CrawlError::Parse {
+            what: "config",
+            path: Some(path.to_path_buf()),
+            reason: e.to_string(),
+        })
+    }
+}
+
+fn find_config(start: &Path) -> Option<PathBuf> {
+    let start = crate::canonicalize_lenient(start);
+    start
+        .ancestors()
+        .map(|dir| dir.join(CONFIG_FILE))
+        .find(|p| p.is_file())
+}
@@ -0,0 +1,101 @@
+//! The error type of the crawl API, so embedders can tell failure modes
+//! apart without matching on message text. The CLI turns it into anyhow.
+//! Like anyhow's, the alternate form `{:#}` appends the underlying cause.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Result of the crawl API.
+pub type Result<T, E = CrawlError> = std::result::Result<T, E>;
+
+/// Why walking the roots or parsing a file failed.
+#[derive(Debug)]
+pub enum CrawlError {
+    /// No root directory was given to walk
+    NoRoots,
+    /// None of the given roots exists; this is the first of them
+    RootNotFound(PathBuf),
+    /// A file could not be opened or read
+    Io { path: PathBuf, source: io::Error },
+    /// A file could not be written
+    Write { path: PathBuf, source: io::Error },
+    /// A compilation database, `.crawlrc` or JSON mapping is malformed
+    Parse {
+        what: &'static str,
+        path: Option<PathBuf>,
+        reason: String,
+    },
+    /// A mapping or the cache could not be turned into JSON
+    Serialize(serde_json::Error),
+    /// A newline-separated file list could not be read
+    FileList(io::Error),
+    /// An --ignore-regex, --glob or --exclude-glob pattern does not compile
+    BadPattern {
+        flag: &'static str,
+        pattern: String,
+        reason: String,
+    },
+    /// Reading a file took longer than the read timeout
+    Timeout { path: PathBuf, after: Duration },
+    /// Worker or reader threads could not be started
+    Threads(String),
+    /// A tarball is compressed in a way we can't undo, or is not a valid tar
+    Archive { path: PathBuf, reason: String },
+}
+
+impl fmt::Display for CrawlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CrawlError::NoRoots => write!(f, "provide at least one root directory"),
+            CrawlError::RootNotFound(root) => write!(f, "root {} does not exist", root.display()),
+            CrawlError::Io { path, .. } => write!(f, "failed to read file {}", path.display()),
+            CrawlError::Write { path, .. } => write!(f, "failed to write {}", path.display()),
+            CrawlError::Parse {
+                what,
+                path: Some(path),
+                reason,
+            } => write!(f, "invalid {} {}: {}", what, path.display(), reason),
+            CrawlError::Parse {
+                what,
+                path: None,
+                reason,
+            } => write!(f, "invalid {}: {}", what, reason),
+            CrawlError::Serialize(_) => write!(f, "failed to serialize JSON"),
+            CrawlError::FileList(_) => write!(f, "failed to read file list"),
+            CrawlError::BadPattern {
+                flag,
+                pattern,
+                reason,
+            } => write!(f, "invalid {} {:?}: {}", flag, pattern, reason),
+            CrawlError::Timeout { path, after } => write!(
+                f,
+                "timed out reading {} after {}s",
+                path.display(),
+                after.as_secs_f64()
+            ),
+            CrawlError::Threads(reason) => write!(f, "failed to start threads: {}", reason),
+            CrawlError::Archive { path, reason } => {
+                write!(f, "failed to read archive {}: {}", path.display(), reason)
+            }
+        }?;
+        match self.source() {
+            Some(source) if f.alternate() => write!(f, ": {}", source),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Error for CrawlError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CrawlError::Io { source, .. }
+            | CrawlError::Write { source, .. }
+            | CrawlError::FileList(source) => Some(source),
+            CrawlError::Serialize(source) => Some(source),
+            _ => None,
+        }
+    }
+}
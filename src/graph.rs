@@ -0,0 +1,442 @@
+//! Graph algorithms over an [`IncludeMapping`].
+
+use crate::{IncludeMapping, canonicalize_lenient};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+
+/// Flip the mapping into a sorted includer -> {includees} graph.
+/// Every node appears as a key, so leaves map to an empty set.
+pub fn dependency_graph(mapping: &IncludeMapping) -> BTreeMap<PathBuf, BTreeSet<PathBuf>> {
+    let mut graph: BTreeMap<PathBuf, BTreeSet<PathBuf>> = BTreeMap::new();
+    for (includee, who) in &mapping.inner {
+        graph.entry(includee.clone()).or_default();
+        for includer in who {
+            graph
+                .entry(includer.clone())
+                .or_default()
+                .insert(includee.clone());
+        }
+    }
+    graph
+}
+
+/// Every node reachable from `start` by repeatedly following `next`,
+/// excluding `start` itself unless a cycle leads back to it.
+/// Visited nodes are tracked, so cycles terminate.
+pub fn reachable<'a>(
+    start: &'a Path,
+    next: impl Fn(&Path) -> Vec<&'a PathBuf>,
+) -> BTreeSet<PathBuf> {
+    let mut seen: BTreeSet<PathBuf> = BTreeSet::new();
+    let mut stack = vec![start];
+    while let Some(v) = stack.pop() {
+        for w in next(v) {
+            if seen.insert(w.clone()) {
+                stack.push(w);
+            }
+        }
+    }
+    seen
+}
+
+/// Weakly connected components: the groups of nodes linked by edges when
+/// direction is ignored, found by union-find. Each component and the list
+/// are sorted.
+pub fn weakly_connected(graph: &BTreeMap<PathBuf, BTreeSet<PathBuf>>) -> Vec<Vec<PathBuf>> {
+    fn find(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+
+    let index = graph
+        .keys()
+        .enumerate()
+        .map(|(i, p)| (p, i))
+        .collect::<HashMap<_, _>>();
+    let mut parent = (0..graph.len()).collect::<Vec<_>>();
+    for (from, tos) in graph {
+        for to in tos {
+            let (a, b) = (find(&mut parent, index[from]), find(&mut parent, index[to]));
+            parent[a.max(b)] = a.min(b);
+        }
+    }
+
+    let mut components: BTreeMap<usize, Vec<PathBuf>> = BTreeMap::new();
+    for (node, i) in &index {
+        let root = find(&mut parent, *i);
+        components.entry(root).or_default().push((*node).clone());
+    }
+    let mut out = components.into_values().collect::<Vec<_>>();
+    for c in &mut out {
+        c.sort();
+    }
+    out.sort();
+    out
+}
+
+/// Strongly connected components via Tarjan's algorithm.
+/// Nodes and neighbours are visited in sorted order, so the result is deterministic.
+pub fn strongly_connected(graph: &BTreeMap<PathBuf, BTreeSet<PathBuf>>) -> Vec<Vec<PathBuf>> {
+    struct Tarjan<'a> {
+        graph: &'a BTreeMap<PathBuf, BTreeSet<PathBuf>>,
+        index: HashMap<&'a Path, usize>,
+        low: HashMap<&'a Path, usize>,
+        stack: Vec<&'a Path>,
+        on_stack: HashSet<&'a Path>,
+        out: Vec<Vec<PathBuf>>,
+    }
+
+    impl<'a> Tarjan<'a> {
+        fn visit(&mut self, v: &'a Path) {
+            let i = self.index.len();
+            self.index.insert(v, i);
+            self.low.insert(v, i);
+            self.stack.push(v);
+            self.on_stack.insert(v);
+
+            for w in self.graph.get(v).into_iter().flatten() {
+                let w = w.as_path();
+                if !self.index.contains_key(w) {
+                    self.visit(w);
+                    let lw = self.low[w];
+                    let lv = self.low.get_mut(v).expect("visited");
+                    *lv = (*lv).min(lw);
+                } else if self.on_stack.contains(w) {
+                    let iw = self.index[w];
+                    let lv = self.low.get_mut(v).expect("visited");
+                    *lv = (*lv).min(iw);
+                }
+            }
+
+            if self.low[v] == self.index[v] {
+                let mut component = Vec::new();
+                while let Some(w) = self.stack.pop() {
+                    self.on_stack.remove(w);
+                    component.push(w.to_path_buf());
+                    if w == v {
+                        break;
+                    }
+                }
+                component.sort();
+                self.out.push(component);
+            }
+        }
+    }
+
+    let mut t = Tarjan {
+        graph,
+        index: HashMap::new(),
+        low: HashMap::new(),
+        stack: Vec::new(),
+        on_stack: HashSet::new(),
+        out: Vec::new(),
+    };
+    for v in graph.keys() {
+        if !t.index.contains_key(v.as_path()) {
+            t.visit(v);
+        }
+    }
+    t.out.sort();
+    t.out
+}
+
+/// Every cycle in the graph, one per strongly connected component, as a
+/// closed chain starting and ending at the component's smallest path.
+pub fn find_cycles(graph: &BTreeMap<PathBuf, BTreeSet<PathBuf>>) -> Vec<Vec<PathBuf>> {
+    let mut cycles = Vec::new();
+    for component in strongly_connected(graph) {
+        let start = &component[0];
+        if component.len() == 1 {
+            // Single node only counts if it includes itself
+            if graph.get(start).is_some_and(|n| n.contains(start)) {
+                cycles.push(vec![start.clone(), start.clone()]);
+            }
+            continue;
+        }
+        let members = component.iter().collect::<HashSet<_>>();
+        if let Some(chain) = cycle_through(graph, start, &members) {
+            cycles.push(chain);
+        }
+    }
+    cycles
+}
+
+/// Shortest path from `start` back to itself, staying inside `members`.
+fn cycle_through(
+    graph: &BTreeMap<PathBuf, BTreeSet<PathBuf>>,
+    start: &PathBuf,
+    members: &HashSet<&PathBuf>,
+) -> Option<Vec<PathBuf>> {
+    let mut prev: HashMap<&PathBuf, &PathBuf> = HashMap::new();
+    let mut queue = VecDeque::from([start]);
+    while let Some(v) = queue.pop_front() {
+        for w in graph.get(v).into_iter().flatten() {
+            if !members.contains(w) {
+                continue;
+            }
+            if w == start {
+                let mut chain = vec![start.clone()];
+                let mut cur = v;
+                while cur != start {
+                    chain.push(cur.clone());
+                    cur = prev[cur];
+                }
+                chain.push(start.clone());
+                chain.reverse();
+                return Some(chain);
+            }
+            if !prev.contains_key(w) {
+                prev.insert(w, v);
+                queue.push_back(w);
+            }
+        }
+    }
+    None
+}
+
+/// Topological order of the graph with every includee before its includers,
+/// breaking ties by path so the order is stable. If a cycle prevents a total
+/// order, returns the cycles among the nodes that could not be placed.
+pub fn toposort(
+    graph: &BTreeMap<PathBuf, BTreeSet<PathBuf>>,
+) -> Result<Vec<PathBuf>, Vec<Vec<PathBuf>>> {
+    let mut pending: HashMap<&PathBuf, usize> = HashMap::new();
+    let mut includers: HashMap<&PathBuf, Vec<&PathBuf>> = HashMap::new();
+    for (includer, includees) in graph {
+        pending.insert(includer, includees.len());
+        for includee in includees {
+            includers.entry(includee).or_default().push(includer);
+        }
+    }
+
+    let mut ready = pending
+        .iter()
+        .filter(|(_, n)| **n == 0)
+        .map(|(p, _)| *p)
+        .collect::<BTreeSet<_>>();
+    let mut order = Vec::with_capacity(graph.len());
+    while let Some(next) = ready.pop_first() {
+        order.push(next.clone());
+        for includer in includers.get(next).into_iter().flatten() {
+            let n = pending.get_mut(includer).expect("every node is a key");
+            *n -= 1;
+            if *n == 0 {
+                ready.insert(includer);
+            }
+        }
+    }
+
+    if order.len() < graph.len() {
+        let placed = order.iter().collect::<HashSet<_>>();
+        let rest = graph
+            .iter()
+            .filter(|(p, _)| !placed.contains(p))
+            .map(|(p, deps)| {
+                let deps = deps.iter().filter(|d| !placed.contains(d)).cloned();
+                (p.clone(), deps.collect())
+            })
+            .collect();
+        return Err(find_cycles(&rest));
+    }
+    Ok(order)
+}
+
+/// Longest include chain in the graph, from an includer down to a leaf
+/// header, e.g. `main.c -> a.h -> b.h`. Ties go to the path-wise first
+/// chain. Fails with the offending cycles, like [`toposort`], since a
+/// cyclic graph has no longest path.
+pub fn longest_chain(
+    graph: &BTreeMap<PathBuf, BTreeSet<PathBuf>>,
+) -> Result<Vec<PathBuf>, Vec<Vec<PathBuf>>> {
+    // Includees come first, so each node's includees are already scored
+    let order = toposort(graph)?;
+    let mut best: HashMap<&PathBuf, (usize, Option<&PathBuf>)> = HashMap::new();
+    for node in &order {
+        let mut score = (0, None);
+        for includee in &graph[node] {
+            let depth = best[includee].0 + 1;
+            if depth > score.0 {
+                score = (depth, Some(includee));
+            }
+        }
+        best.insert(node, score);
+    }
+
+    let mut start: Option<&PathBuf> = None;
+    for node in graph.keys() {
+        if start.is_none_or(|s| best[node].0 > best[s].0) {
+            start = Some(node);
+        }
+    }
+
+    let mut chain = Vec::new();
+    let mut next = start;
+    while let Some(node) = next {
+        chain.push(node.clone());
+        next = best[node].1;
+    }
+    Ok(chain)
+}
+
+/// Collapse a file-level mapping into directory -> directory edges: a
+/// directory includes another if any of its files includes a file there.
+/// System and unresolved includes are not files in any directory, so they
+/// stay as their own nodes. Edges within one directory are dropped unless
+/// `keep_self` is set. Line numbers do not survive the collapse.
+pub fn by_directory(mapping: &IncludeMapping, keep_self: bool) -> IncludeMapping {
+    let raw = |p: &Path| mapping.system.contains(p) || mapping.unresolved.contains(p);
+    let dir_of = |p: &Path| -> PathBuf {
+        if raw(p) {
+            return p.to_path_buf();
+        }
+        p.parent().map(Path::to_path_buf).unwrap_or_default()
+    };
+
+    let mut out = IncludeMapping::new();
+    for (includee, who) in &mapping.inner {
+        let to = dir_of(includee);
+        for includer in who {
+            let from = dir_of(includer);
+            if from == to && !keep_self {
+                continue;
+            }
+            match (
+                mapping.system.contains(includee),
+                mapping.unresolved.contains(includee),
+            ) {
+                (true, _) => out.insert_system(to.clone(), from),
+                (_, true) => out.insert_unresolved(to.clone(), from),
+                _ => out.insert(to.clone(), from),
+            }
+        }
+    }
+    out.problems = mapping.problems;
+    out
+}
+
+/// The first directory of `p` below `project_root`, e.g. `net` for
+/// `net/tcp/socket.c`. Files directly in the root and paths outside it
+/// have none.
+pub fn top_level_dir(p: &Path, project_root: &Path) -> Option<String> {
+    let mut parts = p.strip_prefix(project_root).ok()?.components();
+    if parts.clone().count() < 2 {
+        return None;
+    }
+    parts
+        .next()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+}
+
+/// Keep only edges between different top-level directories
+/// ([`top_level_dir`]), the cross-cutting dependencies. Files directly in
+/// the root share one module; system, unresolved and out-of-root includees
+/// are each a module of their own, so edges to them are kept.
+pub fn keep_cross_module(mapping: &mut IncludeMapping, project_root: &Path) {
+    let module = |p: &Path| -> Option<String> {
+        match p.starts_with(project_root) {
+            true => Some(top_level_dir(p, project_root).unwrap_or_default()),
+            false => None,
+        }
+    };
+    let raw = mapping
+        .system
+        .union(&mapping.unresolved)
+        .cloned()
+        .collect::<HashSet<_>>();
+    mapping.retain_includees(|includee, who| {
+        if raw.contains(includee) {
+            return true;
+        }
+        let Some(to) = module(includee) else {
+            return true;
+        };
+        who.retain(|w| module(w).as_ref() != Some(&to));
+        true
+    });
+}
+
+/// Drop edges that cross from one root into another. Each file belongs to
+/// the outermost root containing it; system and unresolved includes belong
+/// to none, so their edges go too.
+pub fn keep_intra_root(mapping: &mut IncludeMapping, roots: &[PathBuf]) {
+    // Sorted order puts every ancestor before its descendants
+    let roots = roots
+        .iter()
+        .map(|r| canonicalize_lenient(r))
+        .collect::<BTreeSet<_>>();
+    let root_of = |p: &Path| roots.iter().find(|r| p.starts_with(r));
+    mapping.retain_includees(|includee, who| {
+        let Some(root) = root_of(includee) else {
+            return false;
+        };
+        who.retain(|w| root_of(w) == Some(root));
+        true
+    });
+}
+
+/// Keep the includes of `changed` files and of every file that reaches one
+/// of them through its includes.
+pub fn keep_changed_and_rdeps(mapping: &mut IncludeMapping, changed: &HashSet<PathBuf>) {
+    let mut keep = changed.clone();
+    for file in changed {
+        keep.extend(reachable(file, |p| {
+            mapping.inner.get(p).into_iter().flatten().collect()
+        }));
+    }
+    mapping.retain_includees(|_, who| {
+        who.retain(|w| keep.contains(w));
+        true
+    });
+}
+
+/// Keep only `roots` and the files they pull in, directly or transitively.
+/// `roots` are expected to be nodes of the graph already.
+pub fn prune_unreachable(mapping: &mut IncludeMapping, roots: &[PathBuf]) {
+    let deps = mapping.reverse();
+    let mut keep = HashSet::new();
+    for root in roots {
+        keep.extend(reachable(root, |p| {
+            deps.get(p).into_iter().flatten().collect()
+        }));
+        keep.insert(root.clone());
+    }
+    mapping.retain_nodes(&keep);
+}
+
+/// Merge files that differ only in extension, such as `foo.c` and `foo.h`
+/// in one directory, into a single extension-less `foo` node that gets the
+/// union of their edges. Edges between the merged files are dropped, and
+/// system and unresolved includes are kept as they are, as in
+/// [`by_directory`]. Line numbers do not survive the merge.
+pub fn merge_translation_units(mapping: &IncludeMapping) -> IncludeMapping {
+    let unit_of = |p: &Path| -> PathBuf {
+        if mapping.system.contains(p) || mapping.unresolved.contains(p) {
+            return p.to_path_buf();
+        }
+        p.with_extension("")
+    };
+
+    let mut out = IncludeMapping::new();
+    for (includee, who) in &mapping.inner {
+        let to = unit_of(includee);
+        for includer in who {
+            let from = unit_of(includer);
+            if from == to {
+                continue;
+            }
+            match (
+                mapping.system.contains(includee),
+                mapping.unresolved.contains(includee),
+            ) {
+                (true, _) => out.insert_system(to.clone(), from),
+                (_, true) => out.insert_unresolved(to.clone(), from),
+                _ => out.insert(to.clone(), from),
+            }
+        }
+    }
+    out.problems = mapping.problems;
+    out
+}
@@ -0,0 +1,1623 @@
+//! Core of the C/C++ include crawler: walk source roots, parse `#include`
+//! directives, and build an includee -> includers mapping that the
+//! [`graph`] and [`render`] modules analyse and serialize.
+
+use error::Result;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::Match;
+use ignore::gitignore::Gitignore;
+use log::{debug, warn};
+use rayon::prelude::*;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use walkdir::WalkDir;
+
+pub mod archive;
+pub mod cache;
+pub mod compdb;
+pub mod config;
+pub mod error;
+pub mod graph;
+pub mod render;
+
+pub use error::CrawlError;
+
+/// Represents a mapping of include paths to their corresponding files.
+#[derive(Debug, Clone, Default)]
+pub struct IncludeMapping {
+    /// includee -> {includers}
+    pub inner: HashMap<PathBuf, HashSet<PathBuf>>,
+    /// Includees that came from angle-bracket (`<...>`) includes.
+    pub system: HashSet<PathBuf>,
+    /// Quoted includees that could not be resolved to a file on disk and
+    /// are kept as their raw include text.
+    pub unresolved: HashSet<PathBuf>,
+    /// 1-based line numbers of each (includee, includer) edge's `#include`.
+    pub lines: HashMap<(PathBuf, PathBuf), BTreeSet<usize>>,
+    /// Counts of include lines that were dropped or left unresolved.
+    pub problems: ProblemCounts,
+    /// Size and line count of every parsed file.
+    pub metadata: HashMap<PathBuf, FileMeta>,
+}
+
+/// Per-file facts gathered while parsing, for weighting graph nodes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileMeta {
+    /// Size in bytes
+    pub size: u64,
+    pub lines: usize,
+    /// Last modification time, where the platform reports one
+    #[serde(default)]
+    pub mtime: Option<SystemTime>,
+}
+
+/// Tally of `#include` lines that did not cleanly become an edge.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProblemCounts {
+    /// Lines that looked like an include but had no parsable target
+    pub unparsed: usize,
+    /// Quoted includes that matched no file on disk
+    pub unresolved: usize,
+    /// Files that could not be opened or read, and were skipped
+    pub unreadable: usize,
+}
+
+/// Represents a mapping of include paths to their corresponding files.
+impl IncludeMapping {
+    pub fn new() -> Self {
+        IncludeMapping {
+            inner: HashMap::new(),
+            system: HashSet::new(),
+            unresolved: HashSet::new(),
+            lines: HashMap::new(),
+            problems: ProblemCounts::default(),
+            metadata: HashMap::new(),
+        }
+    }
+    pub fn insert(&mut self, key: PathBuf, value: PathBuf) {
+        self.inner.entry(key).or_default().insert(value);
+    }
+    pub fn insert_system(&mut self, key: PathBuf, value: PathBuf) {
+        self.system.insert(key.clone());
+        self.insert(key, value);
+    }
+    pub fn insert_unresolved(&mut self, key: PathBuf, value: PathBuf) {
+        self.unresolved.insert(key.clone());
+        self.insert(key, value);
+    }
+    /// Insert an edge and remember the line of `value` it came from.
+    pub fn insert_line(&mut self, key: PathBuf, value: PathBuf, line: usize) {
+        self.lines
+            .entry((key.clone(), value.clone()))
+            .or_default()
+            .insert(line);
+        self.insert(key, value);
+    }
+    /// The same edges in the includer -> {includees} direction.
+    pub fn reverse(&self) -> HashMap<PathBuf, HashSet<PathBuf>> {
+        let mut out: HashMap<PathBuf, HashSet<PathBuf>> = HashMap::new();
+        for (includee, who) in &self.inner {
+            for includer in who {
+                out.entry(includer.clone())
+                    .or_default()
+                    .insert(includee.clone());
+            }
+        }
+        out
+    }
+    /// Files that include themselves, sorted.
+    pub fn self_includes(&self) -> Vec<&PathBuf> {
+        let mut out = self
+            .inner
+            .iter()
+            .filter(|(includee, who)| who.contains(*includee))
+            .map(|(includee, _)| includee)
+            .collect::<Vec<_>>();
+        out.sort();
+        out
+    }
+    /// Remove every edge from a file to itself; returns how many there were.
+    pub fn remove_self_includes(&mut self) -> usize {
+        let mut removed = 0;
+        self.retain_includees(|includee, who| {
+            removed += usize::from(who.remove(includee));
+            true
+        });
+        removed
+    }
+    /// Drop every edge that has an endpoint outside `keep`.
+    pub fn retain_nodes(&mut self, keep: &HashSet<PathBuf>) {
+        self.retain_includees(|includee, who| {
+            who.retain(|includer| keep.contains(includer));
+            keep.contains(includee)
+        });
+    }
+    /// Keep only the includees for which `f` returns true; `f` may also
+    /// trim the includer set. Includees left without includers are dropped.
+    pub fn retain_includees(&mut self, mut f: impl FnMut(&PathBuf, &mut HashSet<PathBuf>) -> bool) {
+        self.inner
+            .retain(|includee, who| f(includee, who) && !who.is_empty());
+        let inner = &self.inner;
+        self.lines.retain(|(includee, includer), _| {
+            inner
+                .get(includee)
+                .is_some_and(|who| who.contains(includer))
+        });
+        self.system.retain(|p| inner.contains_key(p));
+        self.unresolved.retain(|p| inner.contains_key(p));
+    }
+    /// Merge paths that differ only in letter case, as they name the same
+    /// file on a case-insensitive filesystem. Each group keeps the spelling
+    /// that sorts first, so the result is stable. Returns how many paths
+    /// were folded into another.
+    pub fn fold_case(&mut self) -> usize {
+        self.merge_aliases(|p| Some(p.to_string_lossy().to_lowercase()))
+    }
+    /// Merge files that are hardlinks of each other, i.e. share a device
+    /// and inode, into the path that sorts first. System and unresolved
+    /// includes are not files, so they are left alone. Returns how many
+    /// paths were merged into another; always 0 where files have no inodes.
+    pub fn dedup_hardlinks(&mut self) -> usize {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let raw = self
+                .system
+                .union(&self.unresolved)
+                .cloned()
+                .collect::<HashSet<_>>();
+            self.merge_aliases(|p| {
+                if raw.contains(p) {
+                    return None;
+                }
+                fs::metadata(p).ok().map(|m| (m.dev(), m.ino()))
+            })
+        }
+        #[cfg(not(unix))]
+        0
+    }
+    /// Merge every group of paths with the same `key` into the one that
+    /// sorts first; paths without a key stay as they are. Returns how many
+    /// paths were merged into another.
+    fn merge_aliases<K: std::hash::Hash + Eq>(
+        &mut self,
+        key: impl Fn(&Path) -> Option<K>,
+    ) -> usize {
+        let mut groups: HashMap<K, BTreeSet<PathBuf>> = HashMap::new();
+        for (includee, who) in &self.inner {
+            for p in std::iter::once(includee).chain(who) {
+                if let Some(k) = key(p) {
+                    groups.entry(k).or_default().insert(p.clone());
+                }
+            }
+        }
+        let mut rename: HashMap<PathBuf, PathBuf> = HashMap::new();
+        for variants in groups.values().filter(|v| v.len() > 1) {
+            let mut variants = variants.iter();
+            let keep = variants.next().expect("group is non-empty");
+            for other in variants {
+                rename.insert(other.clone(), keep.clone());
+            }
+        }
+        if rename.is_empty() {
+            return 0;
+        }
+
+        let to = |p: PathBuf| rename.get(&p).cloned().unwrap_or(p);
+        let mut folded = IncludeMapping::new();
+        for (includee, who) in std::mem::take(&mut self.inner) {
+            folded
+                .inner
+                .entry(to(includee))
+                .or_default()
+                .extend(who.into_iter().map(to));
+        }
+        for ((includee, includer), lines) in std::mem::take(&mut self.lines) {
+            folded
+                .lines
+                .entry((to(includee), to(includer)))
+                .or_default()
+                .extend(lines);
+        }
+        folded.system = std::mem::take(&mut self.system)
+            .into_iter()
+            .map(to)
+            .collect();
+        folded.unresolved = std::mem::take(&mut self.unresolved)
+            .into_iter()
+            .map(to)
+            .collect();
+        folded.metadata = std::mem::take(&mut self.metadata)
+            .into_iter()
+            .map(|(p, meta)| (to(p), meta))
+            .collect();
+        folded.problems = self.problems;
+        *self = folded;
+        rename.len()
+    }
+    /// Fold another mapping into this one, unioning includer sets.
+    pub fn merge(&mut self, other: IncludeMapping) {
+        for (key, who) in other.inner {
+            self.inner.entry(key).or_default().extend(who);
+        }
+        self.system.extend(other.system);
+        self.unresolved.extend(other.unresolved);
+        for (edge, lines) in other.lines {
+            self.lines.entry(edge).or_default().extend(lines);
+        }
+        self.metadata.extend(other.metadata);
+        self.problems.unparsed += other.problems.unparsed;
+        self.problems.unresolved += other.problems.unresolved;
+        self.problems.unreadable += other.problems.unreadable;
+    }
+}
+
+/// Lists each includee followed by its includers, indented, all sorted.
+/// Paths are shown in full; see [`IncludeMapping::display_relative`].
+impl fmt::Display for IncludeMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.display_relative(Path::new("")).fmt(f)
+    }
+}
+
+impl IncludeMapping {
+    /// Like the [`fmt::Display`] output, with paths relative to `root`.
+    pub fn display_relative<'a>(&'a self, root: &'a Path) -> DisplayRelative<'a> {
+        DisplayRelative {
+            mapping: self,
+            root,
+        }
+    }
+}
+
+/// Returned by [`IncludeMapping::display_relative`].
+pub struct DisplayRelative<'a> {
+    mapping: &'a IncludeMapping,
+    root: &'a Path,
+}
+
+impl fmt::Display for DisplayRelative<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sorted = self
+            .mapping
+            .inner
+            .iter()
+            .map(|(includee, who)| (includee, who.iter().collect::<BTreeSet<_>>()))
+            .collect::<BTreeMap<_, _>>();
+        for (includee, who) in sorted {
+            writeln!(f, "{}:", rel(includee, self.root))?;
+            for includer in who {
+                writeln!(f, "  {}", rel(includer, self.root))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Controls how `#include` lines are parsed and resolved.
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// Also pick up angle-bracket includes
+    pub system: bool,
+    /// Search directories for include resolution, in priority order
+    pub include_dirs: Vec<PathBuf>,
+    /// Per-file search directories from a compilation database; a file
+    /// listed here uses these instead of `include_dirs`
+    pub file_include_dirs: HashMap<PathBuf, Vec<PathBuf>>,
+    /// Treat `#include 'foo.h'` like `#include "foo.h"`
+    pub lenient_quotes: bool,
+    /// Skip includes inside `#if 0` blocks
+    pub respect_if0: bool,
+    /// Files larger than this many bytes are not read at all
+    pub max_file_size: Option<u64>,
+    /// Extra line-comment markers, e.g. `;` or `#` for assembler files;
+    /// `//` and `/* */` are always honored
+    pub comment_prefixes: Vec<String>,
+    /// Directory quoted includes are tried in before the search directories,
+    /// e.g. a build's virtual source root; `None` means the including file's
+    pub quote_base: Option<PathBuf>,
+    /// Text, e.g. `@generated`, that marks a file as machine-generated when
+    /// it appears in the first few lines; such a file's includes are dropped
+    pub generated_marker: Option<String>,
+}
+
+impl ParseOptions {
+    /// Identifies the options that affect [`parse_file`], so cached
+    /// results from a run with different ones are not reused.
+    pub fn cache_key(&self) -> String {
+        format!(
+            "lenient_quotes={},respect_if0={},max_file_size={:?},comment_prefixes={:?},\
+             generated_marker={:?}",
+            self.lenient_quotes,
+            self.respect_if0,
+            self.max_file_size,
+            self.comment_prefixes,
+            self.generated_marker
+        )
+    }
+}
+
+/// Controls which files `list_relevant_files` picks up.
+#[derive(Debug, Clone, Default)]
+pub struct WalkOptions {
+    /// Substring ignore patterns
+    pub ignores: Vec<String>,
+    /// Regex ignore patterns, matched against the full path
+    pub ignore_regexes: Vec<String>,
+    /// Globs relative to each root; when non-empty, only matches are kept
+    pub globs: Vec<String>,
+    /// Globs relative to each root of files to drop; beats `globs`
+    pub exclude_globs: Vec<String>,
+    /// Comma-separated extensions; `None` means the default set
+    pub exts: Option<String>,
+    /// Descend into symlinked directories and read symlinked files during
+    /// the walk; include resolution follows symlinks regardless
+    pub follow_symlinks: bool,
+    /// Skip paths matched by .gitignore rules
+    pub use_gitignore: bool,
+    /// How many directory levels below each root to descend; `Some(0)`
+    /// keeps only the root's immediate entries
+    pub max_depth: Option<usize>,
+}
+
+/// Everything `build_mapping` needs to walk the roots and parse the files.
+#[derive(Debug, Clone, Default)]
+pub struct CrawlOptions {
+    pub walk: WalkOptions,
+    pub parse: ParseOptions,
+    /// Cap on worker threads; `None` means one per CPU
+    pub threads: Option<usize>,
+    /// Where to keep parsed includes between runs; `None` disables caching
+    pub cache: Option<PathBuf>,
+    /// Abort on the first file that can't be read instead of skipping it
+    pub fail_fast: bool,
+    /// Give up on a file whose read takes longer than this, e.g. on a hung
+    /// network mount; `None` waits forever
+    pub read_timeout: Option<Duration>,
+}
+
+/// Walk the roots and build the include mapping from every relevant file.
+pub fn build_mapping(roots: &[PathBuf], opts: &CrawlOptions) -> Result<IncludeMapping> {
+    let found = list_relevant_files(roots, &opts.walk)?;
+    map_files(&found, opts)
+}
+
+/// Parse `files` in parallel; each worker builds a partial mapping and the
+/// partials are merged at the end. With `opts.cache`, files whose mtime and
+/// size are unchanged since the last run reuse their cached includes.
+pub fn map_files(files: &[PathBuf], opts: &CrawlOptions) -> Result<IncludeMapping> {
+    map_files_with(files, opts, |_, _| Ok::<_, CrawlError>(()), |_| {})
+}
+
+/// [`map_files`], also handing each file's own edges to `on_file` as soon
+/// as that file is parsed, e.g. to stream them out. `on_file` runs on the
+/// worker threads, in no particular order, and its error aborts the crawl.
+/// `on_done` runs once for every file, including unreadable ones that are
+/// skipped without reaching `on_file`, e.g. to drive a progress bar.
+pub fn map_files_with<E: From<CrawlError> + Send>(
+    files: &[PathBuf],
+    opts: &CrawlOptions,
+    on_file: impl Fn(&Path, &IncludeMapping) -> Result<(), E> + Sync,
+    on_done: impl Fn(&Path) + Sync,
+) -> Result<IncludeMapping, E> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(opts.threads.unwrap_or(0))
+        .build()
+        .map_err(|e| CrawlError::Threads(e.to_string()))?;
+    let cache = opts
+        .cache
+        .as_deref()
+        .map(|path| cache::Cache::load(path, &opts.parse.cache_key()))
+        .unwrap_or_default();
+
+    let (mapping, fresh) = pool.install(|| {
+        files
+            .par_iter()
+            .try_fold(
+                || (IncludeMapping::new(), Vec::new()),
+                |(mut mapping, mut fresh), path| {
+                    let parsed = match cache.lookup(path) {
+                        Some(parsed) => parsed.clone(),
+                        None => {
+                            let parsed =
+                                match parse_file_within(path, &opts.parse, opts.read_timeout) {
+                                    Ok(parsed) => parsed,
+                                    Err(e) if opts.fail_fast => return Err(e.into()),
+                                    Err(e) => {
+                                        warn!("{:#}; skipping it", e);
+                                        mapping.problems.unreadable += 1;
+                                        on_done(path);
+                                        return Ok((mapping, fresh));
+                                    }
+                                };
+                            if opts.cache.is_some() {
+                                fresh.push((path.clone(), parsed.clone()));
+                            }
+                            parsed
+                        }
+                    };
+                    let mut own = IncludeMapping::new();
+                    record_includes(path, &parsed, &mut own, &opts.parse);
+                    on_file(path, &own)?;
+                    on_done(path);
+                    mapping.merge(own);
+                    Ok::<_, E>((mapping, fresh))
+                },
+            )
+            .try_reduce(
+                || (IncludeMapping::new(), Vec::new()),
+                |(mut a, mut fa), (b, fb)| {
+                    a.merge(b);
+                    fa.extend(fb);
+                    Ok((a, fa))
+                },
+            )
+    })?;
+
+    if let Some(path) = &opts.cache {
+        let mut cache = cache;
+        for (file, parsed) in fresh {
+            cache.update(file, parsed);
+        }
+        if let Err(e) = cache.save(path) {
+            warn!("failed to save the cache: {:#}", e);
+        }
+    }
+    Ok(mapping)
+}
+
+/// The `#include` lines of one file, as written, before resolution.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParsedFile {
+    /// 1-based line number and target of each include, quoted or angle
+    pub includes: Vec<(usize, Include)>,
+    /// 1-based line number and text of include lines with no parsable target
+    pub unparsed: Vec<(usize, String)>,
+    pub meta: FileMeta,
+}
+
+/// Parse every `#include` in `path` and record it in `mapping`.
+pub fn find_include_lines(
+    path: &Path,
+    mapping: &mut IncludeMapping,
+    opts: &ParseOptions,
+) -> Result<()> {
+    let parsed = parse_file(path, opts)?;
+    record_includes(path, &parsed, mapping, opts);
+    Ok(())
+}
+
+/// Collect every include line of `path`, skipping commented-out ones.
+/// Only the options that change how a line is read apply here; angle
+/// includes are always kept so the result can be cached.
+pub fn parse_file(path: &Path, opts: &ParseOptions) -> Result<ParsedFile> {
+    let file = File::open(path).map_err(|source| CrawlError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let stat = file.metadata().ok();
+    let size = stat.as_ref().map_or(0, |m| m.len());
+    let mtime = stat.and_then(|m| m.modified().ok());
+    let mut parsed = ParsedFile::default();
+    if opts.max_file_size.is_some_and(|max| size > max) {
+        debug!(
+            "skipping {}: {} bytes is over --max-file-size",
+            path.display(),
+            size
+        );
+        parsed.meta.size = size;
+        parsed.meta.mtime = mtime;
+        return Ok(parsed);
+    }
+    let mut parsed = parse_reader(BufReader::new(file), path, opts)?;
+    parsed.meta.size = size;
+    parsed.meta.mtime = mtime;
+    Ok(parsed)
+}
+
+/// [`parse_file`], failing if it takes longer than `timeout`. The read runs
+/// on its own thread, which is abandoned on timeout since a blocked read
+/// can't be interrupted; `None` reads on the calling thread.
+pub fn parse_file_within(
+    path: &Path,
+    opts: &ParseOptions,
+    timeout: Option<Duration>,
+) -> Result<ParsedFile> {
+    let Some(timeout) = timeout else {
+        return parse_file(path, opts);
+    };
+    let (tx, rx) = mpsc::channel();
+    let owned_path = path.to_path_buf();
+    // Resolution happens after the read, so a reader thread abandoned on
+    // timeout needn't keep a compilation database's search directories alive
+    let line_opts = ParseOptions {
+        include_dirs: Vec::new(),
+        file_include_dirs: HashMap::new(),
+        ..opts.clone()
+    };
+    thread::Builder::new()
+        .name("read".to_string())
+        .spawn(move || {
+            // The receiver is gone if we already timed out
+            let _ = tx.send(parse_file(&owned_path, &line_opts));
+        })
+        .map_err(|e| CrawlError::Threads(e.to_string()))?;
+    match rx.recv_timeout(timeout) {
+        Ok(parsed) => parsed,
+        Err(_) => Err(CrawlError::Timeout {
+            path: path.to_path_buf(),
+            after: timeout,
+        }),
+    }
+}
+
+/// The line-by-line part of [`parse_file`], for contents that may not be
+/// on disk; `path` only names the source in errors. Only `lines` of the
+/// metadata is filled in.
+fn parse_reader(reader: impl BufRead, path: &Path, opts: &ParseOptions) -> Result<ParsedFile> {
+    let mut parsed = ParsedFile::default();
+    let mut in_block_comment = false;
+    let mut lines = 0;
+    let mut if0 = If0Tracker::default();
+    let mut generated = false;
+    for (idx, line) in reader.lines().enumerate() {
+        lines += 1;
+        let line = line.map_err(|source| CrawlError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        if idx < GENERATED_WINDOW
+            && let Some(marker) = &opts.generated_marker
+            && line.contains(marker.as_str())
+        {
+            generated = true;
+        }
+        let line = strip_comments(raw_line(&line, idx), &mut in_block_comment);
+        let trimmed = line.trim_start();
+        if is_line_comment(trimmed, &opts.comment_prefixes) {
+            continue;
+        }
+        if opts.respect_if0 && if0.skips(trimmed) {
+            continue;
+        }
+        if include_directive(trimmed).is_some() {
+            // Parse angle includes unconditionally so a skipped `<...>` is
+            // not mistaken for a malformed line
+            match parse_include(trimmed, true, opts.lenient_quotes) {
+                Some(include) => parsed.includes.push((idx + 1, include)),
+                None => parsed
+                    .unparsed
+                    .push((idx + 1, trimmed.trim_end().to_string())),
+            }
+        }
+    }
+    if generated {
+        debug!("skipping includes of generated file {}", path.display());
+        parsed.includes.clear();
+        parsed.unparsed.clear();
+    }
+    parsed.meta.lines = lines;
+    Ok(parsed)
+}
+
+/// Follows `#if`/`#endif` nesting to tell whether a line sits inside an
+/// `#if 0` block. Only a literal `0` counts as dead; every other
+/// condition is assumed live.
+#[derive(Debug, Default)]
+struct If0Tracker {
+    /// Current conditional nesting depth
+    depth: usize,
+    /// Depth of the `#if 0` being skipped, if any
+    dead_at: Option<usize>,
+}
+
+impl If0Tracker {
+    /// Feed the next (comment-stripped, left-trimmed) line; true if it is
+    /// dead code. The directives opening and closing the block count as dead.
+    fn skips(&mut self, line: &str) -> bool {
+        let Some(directive) = line.strip_prefix('#') else {
+            return self.dead_at.is_some();
+        };
+        let mut words = directive.split_whitespace();
+        match words.next() {
+            Some(keyword @ ("if" | "ifdef" | "ifndef")) => {
+                self.depth += 1;
+                let zero = keyword == "if" && words.next() == Some("0") && words.next().is_none();
+                if self.dead_at.is_none() && zero {
+                    self.dead_at = Some(self.depth);
+                    return true;
+                }
+            }
+            Some("else" | "elif" | "elifdef" | "elifndef") if self.dead_at == Some(self.depth) => {
+                self.dead_at = None;
+                return true;
+            }
+            Some("endif") => {
+                let closes = self.dead_at == Some(self.depth);
+                self.depth = self.depth.saturating_sub(1);
+                if closes {
+                    self.dead_at = None;
+                    return true;
+                }
+            }
+            _ => {}
+        }
+        self.dead_at.is_some()
+    }
+}
+
+/// Resolve the includes of `path` and add them to `mapping` as edges.
+fn record_includes(
+    path: &Path,
+    parsed: &ParsedFile,
+    mapping: &mut IncludeMapping,
+    opts: &ParseOptions,
+) {
+    let dirs = opts
+        .file_include_dirs
+        .get(path)
+        .unwrap_or(&opts.include_dirs);
+    record_includes_with(path, parsed, mapping, opts, |include| {
+        resolve_include(path, include, dirs, opts.quote_base.as_deref())
+    });
+}
+
+/// [`record_includes`] with a custom way to find the file an include names.
+fn record_includes_with(
+    path: &Path,
+    parsed: &ParsedFile,
+    mapping: &mut IncludeMapping,
+    opts: &ParseOptions,
+    resolve: impl Fn(&Include) -> Option<PathBuf>,
+) {
+    mapping.metadata.insert(path.to_path_buf(), parsed.meta);
+    for (line, text) in &parsed.unparsed {
+        mapping.problems.unparsed += 1;
+        debug!(
+            "{}:{}: cannot parse include: {}",
+            path.display(),
+            line,
+            text
+        );
+    }
+
+    for (line, include) in &parsed.includes {
+        if include.system && !opts.system {
+            continue;
+        }
+        let key = match resolve(include) {
+            Some(resolved) => resolved,
+            // System headers rarely exist locally, so keep them as written
+            None if include.system => {
+                mapping.system.insert(include.path.clone());
+                include.path.clone()
+            }
+            None => {
+                mapping.problems.unresolved += 1;
+                debug!(
+                    "{}:{}: cannot resolve include \"{}\"",
+                    path.display(),
+                    line,
+                    include.path.display()
+                );
+                mapping.unresolved.insert(include.path.clone());
+                include.path.clone()
+            }
+        };
+        mapping.insert_line(key, PathBuf::from(path), *line);
+    }
+}
+
+/// How many leading lines are searched for the generated-file marker.
+const GENERATED_WINDOW: usize = 5;
+
+/// How many non-blank, non-comment lines may precede a guard's `#ifndef`.
+const GUARD_WINDOW: usize = 5;
+
+/// Heuristic include-guard check: true if the file has `#pragma once`
+/// anywhere, or opens (within its first few code lines) with
+/// `#ifndef X` immediately followed by `#define X`.
+pub fn has_include_guard(path: &Path) -> Result<bool> {
+    let file = File::open(path).map_err(|source| CrawlError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let reader = BufReader::new(file);
+
+    let mut in_block_comment = false;
+    let mut code_lines = 0;
+    let mut pending_guard: Option<String> = None;
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line.map_err(|source| CrawlError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let line = strip_comments(raw_line(&line, idx), &mut in_block_comment);
+        let directive = match line.trim().strip_prefix('#') {
+            Some(d) => d.trim_start(),
+            None if line.trim().is_empty() => continue,
+            None => "",
+        };
+        code_lines += 1;
+
+        let mut words = directive.split_whitespace();
+        let (keyword, arg) = (words.next(), words.next());
+        if keyword == Some("pragma") && arg == Some("once") {
+            return Ok(true);
+        }
+        if let Some(name) = pending_guard.take()
+            && keyword == Some("define")
+            && arg == Some(name.as_str())
+        {
+            return Ok(true);
+        }
+        if code_lines <= GUARD_WINDOW
+            && keyword == Some("ifndef")
+            && let Some(name) = arg
+        {
+            pending_guard = Some(name.to_string());
+        }
+    }
+    Ok(false)
+}
+
+/// Strips what `BufRead::lines` leaves behind on CRLF files (a trailing
+/// `\r`) and a UTF-8 BOM at the start of the first line (`idx == 0`).
+fn raw_line(line: &str, idx: usize) -> &str {
+    let line = line.strip_suffix('\r').unwrap_or(line);
+    if idx == 0 {
+        line.strip_prefix('\u{feff}').unwrap_or(line)
+    } else {
+        line
+    }
+}
+
+/// True if `trimmed` starts with one of the extra comment `prefixes`. A
+/// directive with no space after the `#`, like `#include`, never counts,
+/// so `#` can be given for files that use `# ...` comments.
+fn is_line_comment(trimmed: &str, prefixes: &[String]) -> bool {
+    let directive = trimmed
+        .strip_prefix('#')
+        .is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_alphabetic()));
+    !directive && prefixes.iter().any(|p| trimmed.starts_with(p.as_str()))
+}
+
+/// Returns `line` with `//` and `/* */` comments replaced by a single space.
+/// `in_block` carries an unterminated `/*` over to the next line.
+/// Double-quoted strings and character literals, backslash escapes
+/// included, are copied verbatim so `"a//b.h"` survives and the `"` in
+/// `'"'` doesn't hide a later comment.
+/// A `'` right after a hex digit is a C++14 digit separator, not a literal.
+fn strip_comments(line: &str, in_block: &mut bool) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    let mut quote = None;
+    let mut prev = None;
+
+    while let Some(c) = chars.next() {
+        if *in_block {
+            if c == '*' && chars.peek() == Some(&'/') {
+                chars.next();
+                *in_block = false;
+                out.push(' ');
+            }
+            continue;
+        }
+        if let Some(q) = quote {
+            out.push(c);
+            if c == q {
+                quote = None;
+            } else if c == '\\' {
+                // An escaped quote, as in '\'' or "\"", doesn't end the literal
+                out.extend(chars.next());
+            }
+            prev = Some(c);
+            continue;
+        }
+        match (c, chars.peek()) {
+            ('/', Some('/')) => break,
+            ('/', Some('*')) => {
+                chars.next();
+                *in_block = true;
+            }
+            ('"', _) => {
+                quote = Some(c);
+                out.push(c);
+            }
+            ('\'', _) if !prev.is_some_and(|p: char| p.is_ascii_hexdigit()) => {
+                quote = Some(c);
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+        prev = Some(c);
+    }
+    out
+}
+
+/// Resolves an include the way the compiler would: quoted includes try the
+/// including file's directory (or `quote_base` when given) first, then each
+/// search directory in order; angle-bracket includes only consult the
+/// search directories.
+/// Returns the first canonical match, or `None` if no such file exists.
+///
+/// Symlinks are always followed here, whatever `follow_symlinks` says: that
+/// only controls the walk, while the compiler sees through a symlinked
+/// header farm like `include/foo.h -> ../src/foo/foo.h`, so resolution does
+/// too. The result names the real file.
+fn resolve_include(
+    including: &Path,
+    include: &Include,
+    include_dirs: &[PathBuf],
+    quote_base: Option<&Path>,
+) -> Option<PathBuf> {
+    let local = if include.system {
+        None
+    } else {
+        quote_base.or(including.parent())
+    };
+    local
+        .into_iter()
+        .chain(include_dirs.iter().map(PathBuf::as_path))
+        .find_map(|dir| {
+            let candidate = dir.join(&include.path);
+            // metadata follows symlinks; a directory is never an include target
+            let is_file = fs::metadata(&candidate).is_ok_and(|m| m.is_file());
+            is_file.then(|| candidate.canonicalize().ok()).flatten()
+        })
+}
+
+/// A single parsed `#include` target.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Include {
+    pub path: PathBuf,
+    /// True for angle-bracket includes like `#include <stdio.h>`
+    pub system: bool,
+}
+
+/// If `line` is an include directive, returns the text after the `include`
+/// keyword. Whitespace (spaces or tabs) is allowed between `#` and
+/// `include`, so `#  include "foo.h"` is recognized.
+pub fn include_directive(line: &str) -> Option<&str> {
+    line.strip_prefix('#')?.trim_start().strip_prefix("include")
+}
+
+/// Parses an `#include` line like `#include "../thingy/thing.c"`
+/// and returns `Some(Include)` for quoted includes.
+/// Angle-bracket includes are only returned when `system` is set;
+/// otherwise they yield `None`, as does invalid syntax.
+pub fn parse_include_path(line: &str, system: bool) -> Option<Include> {
+    parse_include(line, system, false)
+}
+
+/// [`parse_include_path`], additionally accepting `'...'` as a quoted
+/// include when `lenient_quotes` is set.
+fn parse_include(line: &str, system: bool, lenient_quotes: bool) -> Option<Include> {
+    // Slice off "#include"
+    let rest = include_directive(line)?.trim_start();
+
+    if let Some(after_start) = rest.strip_prefix('<') {
+        // System include — ignore unless asked for
+        if !system {
+            return None;
+        }
+        let end = after_start.find('>')?;
+        return Some(Include {
+            path: include_path(&after_start[..end]),
+            system: true,
+        });
+    }
+
+    let quotes: &[char] = if lenient_quotes { &['"', '\''] } else { &['"'] };
+    if let Some(start) = rest.find(quotes) {
+        let quote = rest[start..].chars().next()?;
+        let after_start = &rest[start + 1..];
+        if let Some(end) = after_start.find(quote) {
+            // Anything after the closing quote (comments, whitespace) is dropped
+            return Some(Include {
+                path: include_path(&after_start[..end]),
+                system: false,
+            });
+        }
+    }
+
+    None
+}
+
+/// Include text as a path, with `/` and `\` both taken as separators so
+/// `"sub\foo.h"` and `"sub/foo.h"` resolve and dedup the same everywhere.
+/// `.` and `a/..` segments are collapsed lexically, so `./foo.h` and
+/// `a/../foo.h` are the same node as `foo.h`.
+fn include_path(raw: &str) -> PathBuf {
+    normalize_lexically(Path::new(
+        &raw.replace(['/', '\\'], std::path::MAIN_SEPARATOR_STR),
+    ))
+}
+
+/// Drop `.` components and resolve `..` against the preceding component
+/// without touching the filesystem. Leading `..` of a relative path is
+/// kept, since there is nothing to resolve it against.
+pub fn normalize_lexically(p: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for c in p.components() {
+        match c {
+            Component::CurDir => {}
+            Component::ParentDir => match out.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    out.pop();
+                }
+                Some(Component::RootDir | Component::Prefix(_)) => {}
+                _ => out.push(".."),
+            },
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+/// Why the walk kept or dropped a file, as reported by `list --explain`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Kept,
+    /// Dropped by the named pattern, flag or rule file
+    Ignored(String),
+    /// Extension not in the relevant set
+    ExtSkip,
+    /// No extension at all
+    NoExt,
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verdict::Kept => write!(f, "KEPT"),
+            Verdict::Ignored(why) => write!(f, "IGNORED({})", why),
+            Verdict::ExtSkip => write!(f, "EXT_SKIP"),
+            Verdict::NoExt => write!(f, "NO_EXT"),
+        }
+    }
+}
+
+/// Recursively collect the files under `roots` that pass the filters in
+/// `opts`, as canonical paths. Non-existent roots are skipped with a warning,
+/// unless none exists at all, which is [`CrawlError::RootNotFound`].
+pub fn list_relevant_files(roots: &[PathBuf], opts: &WalkOptions) -> Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    walk_files(roots, opts, |_, path, verdict| {
+        if verdict == Verdict::Kept {
+            found.push(path);
+        }
+    })?;
+    Ok(found)
+}
+
+/// [`list_relevant_files`] grouped by the root each file was found under,
+/// in walk order. Roots are canonical, and one nested inside another is
+/// walked as part of the outer root, so it gets no group of its own.
+pub fn list_files_by_root(
+    roots: &[PathBuf],
+    opts: &WalkOptions,
+) -> Result<Vec<(PathBuf, Vec<PathBuf>)>> {
+    let mut groups: Vec<(PathBuf, Vec<PathBuf>)> = Vec::new();
+    walk_files(roots, opts, |root, path, verdict| {
+        if verdict != Verdict::Kept {
+            return;
+        }
+        match groups.last_mut() {
+            Some((last, files)) if last == root => files.push(path),
+            _ => groups.push((root.to_path_buf(), vec![path])),
+        }
+    })?;
+    Ok(groups)
+}
+
+/// Every file the walk considered with the verdict of the filter pipeline.
+/// Kept files are canonical, the rest are shown as walked.
+pub fn explain_files(roots: &[PathBuf], opts: &WalkOptions) -> Result<Vec<(PathBuf, Verdict)>> {
+    let mut seen = Vec::new();
+    walk_files(roots, opts, |_, path, verdict| seen.push((path, verdict)))?;
+    Ok(seen)
+}
+
+/// Walk the roots and pass each file, with the root it was found under
+/// and its verdict, to `visit`. Directories excluded by .gitignore are
+/// pruned without being reported.
+fn walk_files(
+    roots: &[PathBuf],
+    opts: &WalkOptions,
+    mut visit: impl FnMut(&Path, PathBuf, Verdict),
+) -> Result<()> {
+    if roots.is_empty() {
+        return Err(CrawlError::NoRoots);
+    }
+
+    let ignored = opts.ignores.iter().collect::<BTreeSet<_>>();
+    let ignored_re = opts
+        .ignore_regexes
+        .iter()
+        .map(|pat| {
+            Regex::new(pat).map_err(|e| CrawlError::BadPattern {
+                flag: "--ignore-regex",
+                pattern: pat.clone(),
+                reason: e.to_string(),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let exts = parse_exts(opts.exts.as_deref());
+    let globs = build_globset(&opts.globs, "--glob")?;
+    let exclude_globs = build_globset(&opts.exclude_globs, "--exclude-glob")?;
+
+    let filter_file = |path: &Path, root: &Path| -> Verdict {
+        // apply --ignore patterns and regex ignores
+        if let Some(pat) = ignored.iter().find(|pat| ignore_matches(path, pat)) {
+            return Verdict::Ignored(pat.to_string());
+        }
+        let s = path.to_string_lossy();
+        if let Some(re) = ignored_re.iter().find(|re| re.is_match(&s)) {
+            return Verdict::Ignored(re.as_str().to_string());
+        }
+
+        // globs match against the path below the root; excludes win
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        if exclude_globs.is_match(relative) {
+            return Verdict::Ignored("--exclude-glob".to_string());
+        }
+        if !opts.globs.is_empty() && !globs.is_match(relative) {
+            return Verdict::Ignored("--glob".to_string());
+        }
+
+        // filter by extension set, ignoring case so `Foo.H` counts as `h`
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if exts.contains(&ext.to_ascii_lowercase()) => Verdict::Kept,
+            Some(_) => Verdict::ExtSkip,
+            None => Verdict::NoExt,
+        }
+    };
+    // Roots are walked concurrently, which pays off on high-latency mounts,
+    // then reported in order so the result doesn't depend on timing
+    let existing = normalize_roots(roots);
+    if existing.is_empty() {
+        return Err(CrawlError::RootNotFound(canonicalize_lenient(&roots[0])));
+    }
+    let walked = existing
+        .into_par_iter()
+        .map(|root| {
+            let mut seen = Vec::new();
+            let gitignore = RefCell::new(GitignoreRules::default());
+            let crawlignore = load_crawlignore(&root);
+            let crawl_ignored = |path: &Path, is_dir: bool| {
+                crawlignore
+                    .as_ref()
+                    .is_some_and(|gi| gi.matched(path, is_dir).is_ignore())
+            };
+            let walker = if opts.follow_symlinks {
+                WalkDir::new(&root).follow_links(true)
+            } else {
+                WalkDir::new(&root)
+            };
+            // WalkDir counts the root itself as depth 0
+            let walker = match opts.max_depth {
+                Some(depth) => walker.max_depth(depth + 1),
+                None => walker,
+            };
+            // Files are checked below so they can be reported
+            let walker = walker.into_iter().filter_entry(|e| {
+                e.depth() == 0
+                    || !e.file_type().is_dir()
+                    || !(crawl_ignored(e.path(), true)
+                        || opts.use_gitignore && gitignore.borrow_mut().is_ignored(e.path(), true))
+            });
+
+            for entry in walker {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(err) => {
+                        if let Some(ancestor) = err.loop_ancestor() {
+                            warn!(
+                                "symlink loop at {} (points back to {}); not descending",
+                                err.path().unwrap_or(ancestor).display(),
+                                ancestor.display()
+                            );
+                        }
+                        continue;
+                    }
+                };
+                let path = entry.path();
+
+                // skip directories
+                if entry.file_type().is_dir() {
+                    continue;
+                }
+                let verdict = if crawl_ignored(path, false) {
+                    Verdict::Ignored(CRAWLIGNORE_FILE.to_string())
+                } else if opts.use_gitignore && gitignore.borrow_mut().is_ignored(path, false) {
+                    Verdict::Ignored(".gitignore".to_string())
+                } else {
+                    filter_file(path, &root)
+                };
+                match verdict {
+                    Verdict::Kept => seen.push((canonicalize_lenient(path), verdict)),
+                    _ => seen.push((path.to_path_buf(), verdict)),
+                }
+            }
+            (root, seen)
+        })
+        .collect::<Vec<_>>();
+
+    for (root, seen) in walked {
+        for (path, verdict) in seen {
+            visit(&root, path, verdict);
+        }
+    }
+    Ok(())
+}
+
+/// Read newline-separated paths, e.g. from `git diff --name-only`, to be
+/// parsed as-is instead of walking roots. Blank lines are ignored, and
+/// paths that no longer exist (deleted files) are skipped with a warning.
+pub fn read_file_list(reader: impl BufRead) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for line in reader.lines() {
+        let line = line.map_err(CrawlError::FileList)?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let path = canonicalize_lenient(Path::new(line));
+        if !path.is_file() {
+            warn!("skipping {}: not a file", path.display());
+            continue;
+        }
+        files.push(path);
+    }
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+/// `--ignore` semantics: a pattern ending in `/` names directories and must
+/// equal whole components of the file's parent directory (`build/` matches
+/// `a/build/x.c` but not `a/rebuild/x.c`); anything else is a plain
+/// substring match.
+pub fn ignore_matches(path: &Path, pattern: &str) -> bool {
+    let Some(dir) = pattern.strip_suffix('/') else {
+        return path.to_string_lossy().contains(pattern);
+    };
+    let wanted = dir.split('/').filter(|c| !c.is_empty()).collect::<Vec<_>>();
+    if wanted.is_empty() {
+        return path.to_string_lossy().contains(pattern);
+    }
+    let parents = path
+        .parent()
+        .into_iter()
+        .flat_map(Path::components)
+        .filter_map(|c| match c {
+            Component::Normal(name) => name.to_str(),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    parents
+        .windows(wanted.len())
+        .any(|w| w == wanted.as_slice())
+}
+
+/// Canonicalize and dedup `roots`, skipping ones that don't exist and any
+/// root nested inside another, so overlapping roots are walked only once.
+fn normalize_roots(roots: &[PathBuf]) -> Vec<PathBuf> {
+    let mut canonical = BTreeSet::new();
+    for root in roots {
+        let root = canonicalize_lenient(root);
+        if !root.exists() {
+            warn!("skipping non-existent root {}", root.display());
+            continue;
+        }
+        canonical.insert(root);
+    }
+
+    // Sorted order puts every ancestor before its descendants
+    let mut kept: Vec<PathBuf> = Vec::new();
+    for root in canonical {
+        if !kept.iter().any(|k| root.starts_with(k)) {
+            kept.push(root);
+        }
+    }
+    kept
+}
+
+fn build_globset(patterns: &[String], flag: &'static str) -> Result<GlobSet> {
+    let bad = |pattern: &str, e: globset::Error| CrawlError::BadPattern {
+        flag,
+        pattern: pattern.to_string(),
+        reason: e.kind().to_string(),
+    };
+    let mut builder = GlobSetBuilder::new();
+    for pat in patterns {
+        builder.add(Glob::new(pat).map_err(|e| bad(pat, e))?);
+    }
+    builder.build().map_err(|e| bad(&patterns.join(","), e))
+}
+
+/// Per-root ignore file in gitignore syntax, for files that only the
+/// crawler should skip. Always honored, on top of --ignore.
+pub const CRAWLIGNORE_FILE: &str = ".crawlignore";
+
+/// The `.crawlignore` directly in `root`, if there is one.
+fn load_crawlignore(root: &Path) -> Option<Gitignore> {
+    let file = root.join(CRAWLIGNORE_FILE);
+    if !file.is_file() {
+        return None;
+    }
+    let (gi, err) = Gitignore::new(&file);
+    if let Some(err) = err {
+        warn!("{}: {}", file.display(), err);
+    }
+    Some(gi)
+}
+
+/// Lazily loaded .gitignore files, keyed by the directory they live in.
+#[derive(Default)]
+struct GitignoreRules {
+    cache: HashMap<PathBuf, Option<Gitignore>>,
+}
+
+impl GitignoreRules {
+    /// Checks `path` against the .gitignore files of its ancestors, nearest
+    /// first, stopping at the enclosing git repository root. The nearest file
+    /// with a matching rule decides, so a `!pattern` can re-include a path.
+    fn is_ignored(&mut self, path: &Path, is_dir: bool) -> bool {
+        for dir in path.ancestors().skip(1) {
+            let rules = self.cache.entry(dir.to_path_buf()).or_insert_with(|| {
+                let file = dir.join(".gitignore");
+                if !file.is_file() {
+                    return None;
+                }
+                let (gi, err) = Gitignore::new(&file);
+                if let Some(err) = err {
+                    warn!("{}: {}", file.display(), err);
+                }
+                Some(gi)
+            });
+            if let Some(gi) = rules {
+                match gi.matched(path, is_dir) {
+                    Match::Ignore(_) => return true,
+                    Match::Whitelist(_) => return false,
+                    Match::None => {}
+                }
+            }
+            if dir.join(".git").exists() {
+                break;
+            }
+        }
+        false
+    }
+}
+
+/// Extensions of translation units, as opposed to headers.
+pub const SOURCE_EXTS: &[&str] = &["c", "cc", "cpp", "cxx"];
+
+/// True if `p` is a translation unit rather than a header.
+pub fn is_source(p: &Path) -> bool {
+    p.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| SOURCE_EXTS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+/// Parse a comma-separated extension list into a lowercased set.
+pub fn parse_exts(exts_csv: Option<&str>) -> BTreeSet<String> {
+    let default = "c,h,hh,hpp,hxx,inc";
+    let raw = exts_csv.unwrap_or(default);
+    raw.split(',')
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| s.trim().trim_start_matches('.').to_ascii_lowercase())
+        .collect()
+}
+
+pub fn canonicalize_lenient(p: &Path) -> PathBuf {
+    p.canonicalize().unwrap_or_else(|_| p.to_path_buf())
+}
+
+/// Display `p` relative to `root`, falling back to the full path.
+pub fn rel(p: &Path, root: &Path) -> String {
+    match p.strip_prefix(root) {
+        Ok(r) => r.to_string_lossy().to_string(),
+        Err(_) => p.to_string_lossy().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory under the system temp dir with `files` written
+    /// into it, as (relative path, contents) pairs.
+    fn fixture(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dep-crawler-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (path, contents) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        dir.canonicalize().unwrap()
+    }
+
+    #[test]
+    fn file_including_its_own_basename_is_a_self_include() {
+        let dir = fixture("self-include", &[("a.h", "#include \"a.h\"\n")]);
+        let mut mapping =
+            build_mapping(std::slice::from_ref(&dir), &CrawlOptions::default()).unwrap();
+        let a = dir.join("a.h");
+        assert_eq!(mapping.self_includes(), vec![&a]);
+        assert_eq!(mapping.remove_self_includes(), 1);
+        assert!(mapping.inner.is_empty());
+    }
+
+    fn quoted(path: &str) -> Option<Include> {
+        Some(Include {
+            path: PathBuf::from(path),
+            system: false,
+        })
+    }
+
+    #[test]
+    fn spaces_between_hash_and_include_are_allowed() {
+        assert_eq!(
+            parse_include_path("#  include \"foo.h\"", false),
+            quoted("foo.h")
+        );
+        assert!(include_directive("#   include <x.h>").is_some());
+    }
+
+    #[test]
+    fn tabs_around_the_directive_are_allowed() {
+        assert_eq!(
+            parse_include_path("#\tinclude\t\"foo.h\"", false),
+            quoted("foo.h")
+        );
+        assert_eq!(
+            parse_include_path("#include\t\"foo.h\"\t// legacy", false),
+            quoted("foo.h")
+        );
+    }
+
+    #[test]
+    fn text_after_the_closing_quote_is_dropped() {
+        assert_eq!(
+            parse_include_path("#include \"foo.h\" /* note */", false),
+            quoted("foo.h")
+        );
+    }
+
+    #[test]
+    fn extensions_match_regardless_of_case() {
+        assert!(parse_exts(Some("H,.Cpp")).contains("cpp"));
+        let dir = fixture(
+            "ext-case",
+            &[("Foo.H", ""), ("bar.c", ""), ("notes.txt", "")],
+        );
+        let found =
+            list_relevant_files(std::slice::from_ref(&dir), &WalkOptions::default()).unwrap();
+        let mut found = found.into_iter().map(|p| rel(&p, &dir)).collect::<Vec<_>>();
+        found.sort();
+        assert_eq!(found, ["Foo.H", "bar.c"]);
+    }
+
+    #[test]
+    fn nested_roots_are_walked_once() {
+        let dir = fixture(
+            "nested-roots",
+            &[("src/a.c", "#include \"net/b.h\"\n"), ("src/net/b.h", "")],
+        );
+        let roots = [dir.join("src"), dir.join("src/net"), dir.join("src/./net")];
+        assert_eq!(normalize_roots(&roots), vec![dir.join("src")]);
+
+        let files = list_relevant_files(&roots, &WalkOptions::default()).unwrap();
+        assert_eq!(files.len(), 2);
+        let parsed = std::sync::Mutex::new(Vec::new());
+        map_files_with(
+            &files,
+            &CrawlOptions::default(),
+            |path, _| {
+                parsed.lock().unwrap().push(path.to_path_buf());
+                Ok::<_, CrawlError>(())
+            },
+            |_| {},
+        )
+        .unwrap();
+        let mut parsed = parsed.into_inner().unwrap();
+        parsed.sort();
+        assert_eq!(parsed, [dir.join("src/a.c"), dir.join("src/net/b.h")]);
+    }
+
+    #[test]
+    fn raw_line_strips_cr_and_a_leading_bom() {
+        assert_eq!(raw_line("#include \"a.h\"\r", 3), "#include \"a.h\"");
+        assert_eq!(raw_line("\u{feff}#pragma once\r", 0), "#pragma once");
+        // A BOM only means something at the very start of the file
+        assert_eq!(raw_line("\u{feff}x", 1), "\u{feff}x");
+    }
+
+    #[test]
+    fn crlf_and_bom_files_parse_like_plain_ones() {
+        let dir = fixture(
+            "crlf-bom",
+            &[
+                ("crlf.c", "#include \"a.h\"\r\n#include \"b.h\"\r\n"),
+                ("bom.h", "\u{feff}#include \"a.h\"\n"),
+            ],
+        );
+        let targets = |name: &str| {
+            parse_file(&dir.join(name), &ParseOptions::default())
+                .unwrap()
+                .includes
+                .into_iter()
+                .map(|(line, include)| (line, include.path))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            targets("crlf.c"),
+            [(1, PathBuf::from("a.h")), (2, PathBuf::from("b.h"))]
+        );
+        assert_eq!(targets("bom.h"), [(1, PathBuf::from("a.h"))]);
+    }
+
+    #[test]
+    fn trailing_slash_ignores_match_whole_directories() {
+        let build = Path::new("/p/build/x.c");
+        let rebuild = Path::new("/p/rebuild/x.c");
+        assert!(ignore_matches(build, "build/"));
+        assert!(!ignore_matches(rebuild, "build/"));
+        assert!(ignore_matches(
+            Path::new("/p/out/build/gen/y.h"),
+            "out/build/"
+        ));
+        // The file name itself is not a directory
+        assert!(!ignore_matches(Path::new("/p/src/build"), "build/"));
+    }
+
+    #[test]
+    fn plain_ignores_still_match_substrings() {
+        assert!(ignore_matches(Path::new("/p/rebuild/x.c"), "build"));
+        assert!(ignore_matches(Path::new("/p/build/x.c"), "build"));
+    }
+
+    #[test]
+    fn backslash_includes_use_the_platform_separator() {
+        let expected = Path::new("sub").join("foo.h");
+        assert_eq!(include_path("sub\\foo.h"), expected);
+        assert_eq!(include_path("sub/foo.h"), expected);
+        assert_eq!(
+            parse_include_path("#include \"sub\\foo.h\"", false),
+            Some(Include {
+                path: expected,
+                system: false,
+            })
+        );
+    }
+
+    #[test]
+    fn single_quotes_need_lenient_quotes() {
+        let line = "#include 'foo.h'";
+        assert_eq!(parse_include(line, false, true), quoted("foo.h"));
+        assert_eq!(parse_include(line, false, false), None);
+    }
+
+    #[test]
+    fn unterminated_quotes_are_not_includes() {
+        assert_eq!(parse_include("#include \"foo.h", false, false), None);
+        assert_eq!(parse_include("#include 'foo.h", false, true), None);
+        // The closing quote must match the opening one
+        assert_eq!(parse_include("#include 'foo.h\"", false, true), None);
+    }
+
+    #[test]
+    fn normalize_lexically_collapses_dot_and_dotdot() {
+        let norm = |p: &str| normalize_lexically(Path::new(p));
+        assert_eq!(norm("./foo.h"), PathBuf::from("foo.h"));
+        assert_eq!(norm("inc/."), PathBuf::from("inc"));
+        assert_eq!(norm("a/../b"), PathBuf::from("b"));
+        assert_eq!(norm("../a/../foo.h"), PathBuf::from("../foo.h"));
+        assert_eq!(norm("/../x.h"), PathBuf::from("/x.h"));
+    }
+
+    #[test]
+    fn fold_case_merges_spellings_of_one_file() {
+        let p = PathBuf::from;
+        let mut mapping = IncludeMapping::new();
+        mapping.insert_line(p("/src/Foo.h"), p("/src/a.c"), 1);
+        mapping.insert_line(p("/src/foo.h"), p("/src/a.c"), 2);
+        mapping.insert_line(p("/src/foo.h"), p("/src/B.c"), 1);
+        mapping.insert_line(p("/src/bar.h"), p("/src/b.c"), 3);
+
+        assert_eq!(mapping.fold_case(), 2);
+        // Uppercase sorts first, so it is the spelling kept
+        let foo = &mapping.inner[&p("/src/Foo.h")];
+        assert_eq!(foo.len(), 2);
+        assert!(foo.contains(&p("/src/a.c")) && foo.contains(&p("/src/B.c")));
+        assert!(!mapping.inner.contains_key(&p("/src/foo.h")));
+        assert!(mapping.inner[&p("/src/bar.h")].contains(&p("/src/B.c")));
+        assert_eq!(
+            mapping.lines[&(p("/src/Foo.h"), p("/src/a.c"))],
+            BTreeSet::from([1, 2])
+        );
+    }
+
+    #[test]
+    fn char_literals_do_not_hide_comments() {
+        let mut in_block = false;
+        assert_eq!(
+            strip_comments("char c = '\"'; /* x", &mut in_block),
+            "char c = '\"'; "
+        );
+        assert!(in_block);
+
+        let mut in_block = false;
+        assert_eq!(
+            strip_comments("c = '\\''; // x", &mut in_block),
+            "c = '\\''; "
+        );
+        assert_eq!(
+            strip_comments("n = 1'000; // x", &mut in_block),
+            "n = 1'000; "
+        );
+        assert!(!in_block);
+    }
+
+    #[test]
+    fn escaped_quotes_do_not_end_strings() {
+        let mut in_block = false;
+        let line = "const char *s = \"\\\" /*\";";
+        assert_eq!(strip_comments(line, &mut in_block), line);
+        assert!(!in_block);
+
+        let dir = fixture(
+            "escaped-quote",
+            &[
+                ("m.c", "const char *s = \"\\\" /*\";\n#include \"a.h\"\n"),
+                ("a.h", ""),
+            ],
+        );
+        let parsed = parse_file(&dir.join("m.c"), &ParseOptions::default()).unwrap();
+        assert_eq!(parsed.includes.len(), 1);
+    }
+
+    #[test]
+    fn skipped_files_still_count_as_done() {
+        let dir = fixture("skipped-done", &[("a.c", "#include \"b.h\"\n")]);
+        let files = [dir.join("a.c"), dir.join("gone.c")];
+        let done = std::sync::atomic::AtomicUsize::new(0);
+        let mapping = map_files_with(
+            &files,
+            &CrawlOptions::default(),
+            |_, _| Ok::<_, CrawlError>(()),
+            |_| {
+                done.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            },
+        )
+        .unwrap();
+        assert_eq!(mapping.problems.unreadable, 1);
+        assert_eq!(done.into_inner(), files.len());
+    }
+
+    #[test]
+    fn missing_roots_fail_only_when_none_exists() {
+        let dir = fixture("missing-root", &[("a.c", "")]);
+        let gone = dir.join("gone");
+        let walk = WalkOptions::default();
+        let err = list_relevant_files(std::slice::from_ref(&gone), &walk).unwrap_err();
+        assert!(matches!(err, CrawlError::RootNotFound(ref p) if *p == gone));
+        assert_eq!(
+            list_relevant_files(&[gone, dir.clone()], &walk).unwrap(),
+            [dir.join("a.c")]
+        );
+    }
+}
@@ -1,13 +1,36 @@
 use anyhow::{Context, Result, anyhow};
-use clap::{Parser, Subcommand};
-use std::collections::{BTreeSet, HashMap, HashSet};
-use std::fmt;
-use std::fmt::Write as _;
+use clap::{ArgAction, ArgGroup, Args, Parser, Subcommand, ValueEnum};
+use dep_crawler::archive;
+use dep_crawler::cache::CACHE_FILE;
+use dep_crawler::compdb;
+use dep_crawler::config::Config;
+use dep_crawler::graph::{
+    by_directory, dependency_graph, find_cycles, keep_changed_and_rdeps, keep_cross_module,
+    keep_intra_root, longest_chain, merge_translation_units, prune_unreachable, reachable,
+    toposort, weakly_connected,
+};
+use dep_crawler::render::{
+    DotOptions, NodeStyle, read_json, write_d2, write_dot, write_dot_left_right, write_graphml,
+    write_html, write_json, write_json_with_metadata, write_jsonl, write_matrix, write_mermaid,
+    write_plantuml,
+};
+use dep_crawler::{
+    CrawlError, CrawlOptions, IncludeMapping, ParseOptions, Verdict, WalkOptions,
+    canonicalize_lenient, explain_files, has_include_guard, ignore_matches, is_source,
+    list_files_by_root, list_relevant_files, map_files, map_files_with, parse_file_within,
+    read_file_list, rel,
+};
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{LevelFilter, error, info, warn};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{BufReader, BufWriter, IsTerminal, Write as _};
 use std::path::{Path, PathBuf};
-use walkdir::WalkDir;
+use std::process::{Command, ExitCode, Stdio};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
 
 /// Minimal file finder: lists relevant C/C++ header/source files.
 #[derive(Parser, Debug)]
@@ -15,313 +38,1882 @@ use walkdir::WalkDir;
 struct Cli {
     #[command(subcommand)]
     cmd: Cmd,
+
+    /// Log more to stderr: -v adds summaries such as the unparsable and
+    /// unresolved include counts, -vv every such include line. Without
+    /// either, a level name in RUST_LOG (e.g. `debug`) applies
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Print only errors: no warnings, progress bar or summary
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
 }
 
-/// Represents a mapping of include paths to their corresponding files.
-#[derive(Debug)]
-struct IncludeMapping {
-    inner: HashMap<PathBuf, HashSet<PathBuf>>,
+/// Writes log records from this crate to stderr as `level: message`,
+/// e.g. `warn: skipping non-existent root x`.
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        // Dependencies log too; their debug output is not ours to show
+        let ours = ["ccrawl", "dep_crawler"]
+            .iter()
+            .any(|c| metadata.target().starts_with(c));
+        ours && metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            let level = record.level().as_str().to_ascii_lowercase();
+            eprintln!("{}: {}", level, record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+/// Level from -q and -v, else from RUST_LOG, else warnings and errors.
+fn log_level(quiet: bool, verbose: u8) -> LevelFilter {
+    match (quiet, verbose) {
+        (true, _) => LevelFilter::Error,
+        (_, 1) => LevelFilter::Info,
+        (_, 2..) => LevelFilter::Debug,
+        _ => std::env::var("RUST_LOG")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(LevelFilter::Warn),
+    }
 }
 
-/// Represents a mapping of include paths to their corresponding files.
-impl IncludeMapping {
-    fn new() -> Self {
-        IncludeMapping {
-            inner: HashMap::new(),
+/// Options shared by every subcommand that walks the roots.
+#[derive(Args, Debug)]
+struct CrawlArgs {
+    /// One or more root directories to scan
+    #[arg(required_unless_present_any = ["files_from", "archive"])]
+    roots: Vec<PathBuf>,
+
+    /// Read newline-separated files to parse from PATH (`-` for stdin)
+    /// instead of walking roots
+    #[arg(long, value_name = "PATH", conflicts_with = "roots")]
+    files_from: Option<PathBuf>,
+
+    /// Crawl the files inside a .tar, .tar.gz or .tgz without extracting it.
+    /// Nodes are in-archive paths, and -I dirs are taken as in-archive paths too,
+    /// e.g. -I libfoo-1.0/include. Of the walk options, only --exts and --ignore apply
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = [
+            "roots",
+            "files_from",
+            "since",
+            "compile_commands",
+            "globs",
+            "exclude_globs",
+            "ignore_regexes",
+            "max_depth",
+            "use_gitignore",
+            "dedup_by_realpath",
+        ]
+    )]
+    archive: Option<PathBuf>,
+
+    /// Repeatable ignore patterns, e.g. --ignore build/ --ignore .git/. A trailing `/`
+    /// matches whole directory names; other patterns match any substring of the path.
+    /// Falls back to the colon-separated CRAWL_IGNORE environment variable
+    #[arg(long = "ignore", value_name = "PATTERN", num_args = 0..)]
+    ignores: Vec<String>,
+
+    /// Repeatable regex ignore patterns, matched against the full path
+    #[arg(long = "ignore-regex", value_name = "REGEX")]
+    ignore_regexes: Vec<String>,
+
+    /// Repeatable shell-style glob, relative to each root; when given, only matching files are kept
+    #[arg(long = "glob", value_name = "GLOB")]
+    globs: Vec<String>,
+
+    /// Repeatable shell-style glob, relative to each root, of files to drop.
+    /// Takes precedence over --glob when both match
+    #[arg(long = "exclude-glob", value_name = "GLOB")]
+    exclude_globs: Vec<String>,
+
+    /// Override relevant file extensions (comma-separated, no dots). Default: c,h,hh,hpp,hxx,inc.
+    /// Falls back to the colon-separated CRAWL_EXTS environment variable
+    #[arg(long = "exts", value_name = "CSV")]
+    exts: Option<String>,
+
+    /// Follow symlinks while walking the roots. Includes are resolved
+    /// through symlinks (e.g. a header farm passed with -I) either way
+    #[arg(long, overrides_with = "no_follow_symlinks")]
+    follow_symlinks: bool,
+
+    /// Don't follow symlinks while walking, even if `.crawlrc` says to
+    #[arg(long, overrides_with = "follow_symlinks")]
+    no_follow_symlinks: bool,
+
+    /// Skip files and directories matched by .gitignore rules
+    #[arg(long)]
+    use_gitignore: bool,
+
+    /// Limit recursion below each root; 0 means only the root's immediate entries
+    #[arg(long, value_name = "N")]
+    max_depth: Option<usize>,
+
+    /// Also pick up angle-bracket (system) includes
+    #[arg(long)]
+    system: bool,
+
+    /// Repeatable include search directory, tried in order after the including file's directory
+    #[arg(short = 'I', long = "include-dir", value_name = "DIR")]
+    include_dirs: Vec<PathBuf>,
+
+    /// Directory quoted includes are resolved against before the -I dirs:
+    /// the including file's, or the project root
+    #[arg(long, value_enum, default_value_t = QuoteBase::File)]
+    quote_base: QuoteBase,
+
+    /// Resolve includes of each translation unit listed in this clang
+    /// compile_commands.json with its own -I/-isystem/-iquote directories.
+    /// Other files, including headers, still use -I
+    #[arg(long, value_name = "FILE")]
+    compile_commands: Option<PathBuf>,
+
+    /// Cap the number of worker threads used to read files. Default: one per CPU
+    #[arg(long, value_name = "N")]
+    threads: Option<usize>,
+
+    /// Don't read files larger than BYTES, e.g. huge generated blobs
+    #[arg(long, value_name = "BYTES")]
+    max_file_size: Option<u64>,
+
+    /// Repeatable extra line-comment marker, e.g. `;` or `#`, for non-C inputs
+    /// like assembler `.inc` files. `//` and `/* */` are always honored; a
+    /// directive written `#include` (no space) is never a comment
+    #[arg(long = "comment-prefix", value_name = "PREFIX")]
+    comment_prefixes: Vec<String>,
+
+    /// Skip includes inside `#if 0` ... `#endif` blocks
+    #[arg(long)]
+    respect_if0: bool,
+
+    /// Drop the includes of files whose first 5 lines contain the
+    /// --generated-marker, e.g. code-generator output
+    #[arg(long)]
+    skip_generated: bool,
+
+    /// Text marking a generated file for --skip-generated
+    #[arg(
+        long,
+        value_name = "MARKER",
+        default_value = "@generated",
+        requires = "skip_generated"
+    )]
+    generated_marker: String,
+
+    /// Keep edges from a file to itself instead of dropping them. cycles,
+    /// toposort and depth always keep them, as a self-include is a cycle
+    #[arg(long)]
+    keep_self: bool,
+
+    /// Also accept single-quoted includes like `#include 'foo.h'`
+    #[arg(long)]
+    lenient_quotes: bool,
+
+    /// Treat paths differing only in letter case as one file, as on macOS
+    /// and Windows filesystems; each shows under its first-sorting spelling
+    #[arg(long)]
+    case_insensitive_paths: bool,
+
+    /// Treat hardlinks of one file (same device and inode) as a single node,
+    /// shown under its first-sorting path. Has no effect where files have no inodes
+    #[arg(long)]
+    dedup_by_realpath: bool,
+
+    /// Re-parse every file instead of reusing .crawl-cache.json
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Abort on the first file that can't be read instead of warning and skipping it
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// Skip, with a warning, any file whose read takes longer than SECS, so a
+    /// flaky network mount can't hang the scan. 0 means no limit
+    #[arg(long, value_name = "SECS", default_value_t = 0)]
+    read_timeout: u64,
+
+    /// Only parse files changed since git REF, per `git diff --name-only REF`
+    #[arg(long, value_name = "REF")]
+    since: Option<String>,
+
+    /// With --since, also keep every file that includes a changed file,
+    /// directly or transitively. All files are parsed to find them
+    #[arg(long, requires = "since")]
+    with_rdeps: bool,
+
+    /// Directory that printed paths are shown relative to. Default: the current directory
+    #[arg(long, value_name = "DIR")]
+    relative_to: Option<PathBuf>,
+}
+
+impl CrawlArgs {
+    /// Options from the flags. When a flag is not given, exts and ignores
+    /// fall back to `CRAWL_EXTS` and `CRAWL_IGNORE`, then to the nearest
+    /// `.crawlrc`, which also supplies follow_symlinks.
+    fn options(&self) -> Result<CrawlOptions> {
+        let config = Config::discover(Path::new("."))?;
+        let ignores = match (&self.ignores, env_list("CRAWL_IGNORE"), config.ignores) {
+            (cli, _, _) if !cli.is_empty() => cli.clone(),
+            (_, Some(env), _) => env,
+            (_, None, file) => file.unwrap_or_default(),
+        };
+        Ok(CrawlOptions {
+            walk: WalkOptions {
+                ignores,
+                ignore_regexes: self.ignore_regexes.clone(),
+                globs: self.globs.clone(),
+                exclude_globs: self.exclude_globs.clone(),
+                exts: self
+                    .exts
+                    .clone()
+                    .or_else(|| env_list("CRAWL_EXTS").map(|e| e.join(",")))
+                    .or_else(|| config.exts.map(|e| e.join(","))),
+                follow_symlinks: match (self.follow_symlinks, self.no_follow_symlinks) {
+                    (true, _) => true,
+                    (_, true) => false,
+                    _ => config.follow_symlinks.unwrap_or(false),
+                },
+                use_gitignore: self.use_gitignore,
+                max_depth: self.max_depth,
+            },
+            parse: ParseOptions {
+                system: self.system,
+                include_dirs: self.include_dirs.clone(),
+                file_include_dirs: match &self.compile_commands {
+                    Some(path) => compdb::load(path)?,
+                    None => HashMap::new(),
+                },
+                lenient_quotes: self.lenient_quotes,
+                respect_if0: self.respect_if0,
+                max_file_size: self.max_file_size,
+                comment_prefixes: self.comment_prefixes.clone(),
+                quote_base: (self.quote_base == QuoteBase::Project).then(|| self.project_root()),
+                generated_marker: self.skip_generated.then(|| self.generated_marker.clone()),
+            },
+            threads: self.threads,
+            cache: (!self.no_cache).then(|| PathBuf::from(CACHE_FILE)),
+            fail_fast: self.fail_fast,
+            read_timeout: (self.read_timeout > 0).then(|| Duration::from_secs(self.read_timeout)),
+        })
+    }
+
+    /// Files to parse: those listed by --files-from, or else those found
+    /// under the roots.
+    /// With --since (and no --with-rdeps), only those changed since REF.
+    fn files(&self, opts: &CrawlOptions) -> Result<Vec<PathBuf>> {
+        if self.archive.is_some() {
+            return Err(anyhow!("--archive is not supported by this command"));
+        }
+        let mut files = match &self.files_from {
+            None => list_relevant_files(&self.roots, &opts.walk)?,
+            Some(path) if path == Path::new("-") => read_file_list(std::io::stdin().lock())?,
+            Some(path) => {
+                let file = fs::File::open(path)
+                    .with_context(|| format!("failed to open {}", path.display()))?;
+                read_file_list(BufReader::new(file))?
+            }
+        };
+        if let Some(git_ref) = &self.since
+            && !self.with_rdeps
+        {
+            let changed = changed_since(git_ref)?;
+            files.retain(|f| changed.contains(f));
         }
+        Ok(files)
+    }
+
+    /// Base for displaying paths; paths outside it are shown absolute.
+    fn project_root(&self) -> PathBuf {
+        canonicalize_lenient(self.relative_to.as_deref().unwrap_or(Path::new(".")))
+    }
+
+    fn build_mapping(&self) -> Result<IncludeMapping> {
+        self.build_mapping_with(false, |_, _| Ok(()))
+    }
+
+    /// Like `build_mapping`, but always keeping self-includes: for the graph
+    /// analyses, where a file including itself is a cycle to report.
+    fn build_graph(&self) -> Result<IncludeMapping> {
+        self.build(false, true, |_, _| Ok(()))
+    }
+
+    /// Like `build_mapping`, passing each file's edges to `on_file` as it is
+    /// parsed. With `progress`, draws a files-parsed bar on stderr.
+    fn build_mapping_with(
+        &self,
+        progress: bool,
+        on_file: impl Fn(&Path, &IncludeMapping) -> Result<()> + Sync,
+    ) -> Result<IncludeMapping> {
+        self.build(progress, self.keep_self, on_file)
+    }
+
+    /// Shared by the `build_*` methods; self-includes are dropped unless
+    /// `keep_self`.
+    fn build(
+        &self,
+        progress: bool,
+        keep_self: bool,
+        on_file: impl Fn(&Path, &IncludeMapping) -> Result<()> + Sync,
+    ) -> Result<IncludeMapping> {
+        let opts = self.options()?;
+        // Hand callers each file's edges as they will appear in the result
+        let on_file = |path: &Path, edges: &IncludeMapping| {
+            if keep_self || edges.self_includes().is_empty() {
+                return on_file(path, edges);
+            }
+            let mut edges = edges.clone();
+            edges.remove_self_includes();
+            on_file(path, &edges)
+        };
+        if let Some(archive) = &self.archive {
+            let mapping = archive::map_archive(archive, &opts, on_file)?;
+            return self.finish_mapping(mapping, keep_self);
+        }
+        let files = self.files(&opts)?;
+        let bar = if progress {
+            ProgressBar::new(files.len() as u64).with_style(
+                ProgressStyle::with_template("{bar:40} {pos}/{len} files ({eta})")
+                    .expect("valid progress template"),
+            )
+        } else {
+            ProgressBar::hidden()
+        };
+        let mapping = map_files_with(&files, &opts, on_file, |_| bar.inc(1));
+        bar.finish_and_clear();
+        self.finish_mapping(mapping?, keep_self)
     }
-    fn insert(&mut self, key: PathBuf, value: PathBuf) {
-        self.inner.entry(key).or_default().insert(value);
+
+    /// Post-processing shared by every input mode: case folding,
+    /// --with-rdeps, the problem summary and self-include removal.
+    fn finish_mapping(
+        &self,
+        mut mapping: IncludeMapping,
+        keep_self: bool,
+    ) -> Result<IncludeMapping> {
+        if self.case_insensitive_paths {
+            let folded = mapping.fold_case();
+            if folded > 0 {
+                info!("merged {} path(s) differing only in case", folded);
+            }
+        }
+        if self.dedup_by_realpath {
+            if !cfg!(unix) {
+                warn!("--dedup-by-realpath needs inodes, which this platform lacks; ignoring it");
+            }
+            let merged = mapping.dedup_hardlinks();
+            if merged > 0 {
+                info!("merged {} hardlinked path(s)", merged);
+            }
+        }
+        if let Some(git_ref) = &self.since
+            && self.with_rdeps
+        {
+            keep_changed_and_rdeps(&mut mapping, &changed_since(git_ref)?);
+        }
+        report_problems(&mapping);
+        if !keep_self {
+            let dropped = mapping.remove_self_includes();
+            if dropped > 0 {
+                warn!(
+                    "dropped {} self-include(s); run `ccrawl self-includes` to list them",
+                    dropped
+                );
+            }
+        }
+        Ok(mapping)
     }
 }
 
-impl fmt::Display for IncludeMapping {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for (key, value) in &self.inner {
-            writeln!(f, "{}: {:?}", key.display(), value)?;
+/// At -v, summarize the include lines that did not become edges.
+fn report_problems(mapping: &IncludeMapping) {
+    info!(
+        "{} unparsable include(s), {} unresolved include(s), {} unreadable file(s)",
+        mapping.problems.unparsed, mapping.problems.unresolved, mapping.problems.unreadable
+    );
+}
+
+/// Options for `scan`: what to crawl and how to render the result.
+#[derive(Args, Debug)]
+#[command(group(ArgGroup::new("checks").args(["max_includes", "rules"]).multiple(true)))]
+struct ScanArgs {
+    #[command(flatten)]
+    crawl: CrawlArgs,
+
+    /// Output format for the dependency graph; repeat or comma-separate
+    /// to write several formats from one scan
+    #[arg(
+        long = "format",
+        value_enum,
+        value_delimiter = ',',
+        default_value = "dot"
+    )]
+    formats: Vec<Format>,
+
+    /// Where to write the graph; `-` for stdout. Repeat once per --format, in
+    /// the same order. Default: <name>.<ext> in the current directory
+    #[arg(short, long = "output", value_name = "PATH")]
+    outputs: Vec<PathBuf>,
+
+    /// Base file name of outputs not given with --output, so scans run side
+    /// by side don't overwrite each other's dep-graph.dot
+    #[arg(
+        long,
+        value_name = "BASENAME",
+        default_value = "dep-graph",
+        conflicts_with = "outputs"
+    )]
+    name: String,
+
+    /// After every output is written, list them in FILE as JSON, e.g.
+    /// `{"outputs": [{"path": "dep-graph.svg", "format": "svg"}]}`,
+    /// for build systems that declare generated files
+    #[arg(long, value_name = "FILE")]
+    manifest: Option<PathBuf>,
+
+    /// Repeatable DOT node style per extension, e.g. --node-style hpp=box:#fde0dd
+    #[arg(long = "node-style", value_name = "EXT=SHAPE:COLOR", value_parser = parse_node_style)]
+    node_styles: Vec<(String, NodeStyle)>,
+
+    /// With --format json, add each parsed file's byte size and line count
+    #[arg(long)]
+    with_metadata: bool,
+
+    /// With --format json, add each parsed file's last-modified time as an
+    /// RFC 3339 UTC string; implies --with-metadata
+    #[arg(long)]
+    with_mtime: bool,
+
+    /// Arrow direction in the DOT output: `includers` draws each header
+    /// pointing at the files that include it, `deps` each file pointing at
+    /// the headers it includes
+    #[arg(long, value_enum, default_value_t = Direction::Includers)]
+    direction: Direction,
+
+    /// Warn about files that include the same header on more than one line,
+    /// and label those DOT edges with the count
+    #[arg(long)]
+    count_duplicates: bool,
+
+    /// Append each DOT node's number of includes to its label, e.g. `foo.c (12)`
+    #[arg(long)]
+    label_with_count: bool,
+
+    /// Group DOT nodes into clusters by their top-level directory
+    #[arg(long)]
+    cluster_by_dir: bool,
+
+    /// Pipe the DOT output through Graphviz `dot` and write the image instead
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    render: Option<Render>,
+
+    /// Only render includees with at least N includers
+    #[arg(long, value_name = "N")]
+    min_fanin: Option<usize>,
+
+    /// Graph nodes: individual files, or directories with an edge wherever
+    /// any file of one includes a file of the other
+    #[arg(long, value_enum, default_value_t = Granularity::File)]
+    granularity: Granularity,
+
+    /// Merge files differing only in extension, like foo.c and foo.h, into one
+    /// `foo` node with the union of their edges
+    #[arg(long)]
+    merge_translation_units: bool,
+
+    /// With --granularity dir, keep edges between files of the same directory
+    #[arg(long)]
+    keep_self_edges: bool,
+
+    /// With several roots, drop edges whose includer and includee are not
+    /// under the same root
+    #[arg(long, conflicts_with_all = ["files_from", "archive"])]
+    intra_root_only: bool,
+
+    /// Only keep edges whose includer and includee are in different top-level
+    /// directories, to audit layering. Works with --cluster-by-dir
+    #[arg(long)]
+    cross_module_only: bool,
+
+    /// Repeatable entry file; only files it reaches through its includes are rendered
+    #[arg(long = "root-file", value_name = "FILE")]
+    root_files: Vec<PathBuf>,
+
+    /// Warn about files with more than N include lines. Counted per file before
+    /// --granularity dir or --merge-translation-units collapse the graph, which
+    /// drops line numbers
+    #[arg(long, value_name = "N")]
+    max_includes: Option<usize>,
+
+    /// Repeatable layering rule `HEADER: PREFIX[, PREFIX...]`, e.g.
+    /// 'net/internal.h: net/'; warns about includers of HEADER outside
+    /// every PREFIX. Paths are relative to --relative-to
+    #[arg(long = "rule", value_name = "RULE", value_parser = parse_rule)]
+    rules: Vec<Rule>,
+
+    /// Abort once the graph has more than N edges, a sign of a
+    /// misconfigured scan, before it grows too big to render
+    #[arg(long, value_name = "N", default_value_t = 1_000_000)]
+    max_edges: usize,
+
+    /// Exit non-zero when --max-includes is exceeded or a --rule is broken
+    #[arg(long, requires = "checks")]
+    strict: bool,
+
+    /// After scanning, keep polling the files and rescan whenever one is
+    /// added, removed or modified. With the cache, only changed files are re-read.
+    /// Needs roots to walk, so neither --files-from nor --archive
+    #[arg(long, conflicts_with_all = ["archive", "files_from"])]
+    watch: bool,
+}
+
+impl ScanArgs {
+    /// Each requested format paired with the path it is written to.
+    fn targets(&self) -> Result<Vec<(Format, PathBuf)>> {
+        if !self.outputs.is_empty() && self.outputs.len() != self.formats.len() {
+            return Err(anyhow!(
+                "give one --output per --format ({} format(s), {} output(s))",
+                self.formats.len(),
+                self.outputs.len()
+            ));
+        }
+        Ok(self
+            .formats
+            .iter()
+            .enumerate()
+            .map(|(i, &format)| {
+                let path = self.outputs.get(i).cloned().unwrap_or_else(|| {
+                    let ext = match (format, self.render) {
+                        (Format::Dot, Some(render)) => render.name(),
+                        _ => format.extension(),
+                    };
+                    PathBuf::from(format!("{}.{}", self.name, ext))
+                });
+                (format, path)
+            })
+            .collect())
+    }
+
+    fn dot_options(&self) -> DotOptions {
+        DotOptions {
+            node_styles: self.node_styles.iter().cloned().collect(),
+            cluster_by_dir: self.cluster_by_dir,
+            deps_direction: self.direction == Direction::Deps,
+            label_with_count: self.label_with_count,
+            edge_weights: self.count_duplicates,
         }
-        Ok(())
     }
 }
 
+/// Parses `ext=shape:color` for --node-style.
+fn parse_node_style(s: &str) -> Result<(String, NodeStyle), String> {
+    let (ext, style) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected EXT=SHAPE:COLOR, got {:?}", s))?;
+    let (shape, color) = style
+        .split_once(':')
+        .ok_or_else(|| format!("expected SHAPE:COLOR after '=', got {:?}", style))?;
+    let ext = ext.trim().trim_start_matches('.').to_ascii_lowercase();
+    Ok((
+        ext,
+        NodeStyle {
+            shape: shape.to_string(),
+            color: color.to_string(),
+        },
+    ))
+}
+
+/// A --rule: only files under one of `allowed` may include `header`.
+#[derive(Debug, Clone)]
+struct Rule {
+    header: PathBuf,
+    allowed: Vec<PathBuf>,
+}
+
+/// Parses `header: prefix, prefix` for --rule.
+fn parse_rule(s: &str) -> Result<Rule, String> {
+    let (header, allowed) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected HEADER: PREFIX[, PREFIX...], got {:?}", s))?;
+    let allowed = allowed
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|p| !p.is_empty())
+        .map(PathBuf::from)
+        .collect::<Vec<_>>();
+    if allowed.is_empty() {
+        return Err(format!("no allowed prefix after ':' in {:?}", s));
+    }
+    Ok(Rule {
+        header: PathBuf::from(header.trim()),
+        allowed,
+    })
+}
+
 #[derive(Subcommand, Debug)]
 enum Cmd {
     /// Recursively list relevant files under given roots
     List {
-        /// One or more root directories to scan
-        #[arg(required = true)]
-        roots: Vec<PathBuf>,
+        #[command(flatten)]
+        crawl: CrawlArgs,
 
-        /// Repeatable ignore patterns (substring match), e.g. --ignore build/ --ignore .git/
-        #[arg(long = "ignore", value_name = "PATTERN", num_args = 0..)]
-        ignores: Vec<String>,
+        /// Print only the number of files found instead of listing them
+        #[arg(long)]
+        count: bool,
 
-        /// Override relevant file extensions (comma-separated, no dots). Default: c,h,hh,hpp,hxx,inc
-        #[arg(long = "exts", value_name = "CSV")]
-        exts: Option<String>,
+        /// Print every file considered with why it was kept or skipped:
+        /// KEPT, IGNORED(pattern), EXT_SKIP or NO_EXT
+        #[arg(long, conflicts_with_all = ["count", "files_from", "archive"])]
+        explain: bool,
 
-        /// Follow symlinks during traversal
-        #[arg(long)]
-        follow_symlinks: bool,
+        /// Group the files under a `ROOT:` header per root, each shown
+        /// relative to its root; with --count, print a count per root
+        #[arg(long, conflicts_with_all = ["explain", "files_from", "archive"])]
+        by_root: bool,
+
+        /// Print how many files the walk found per extension, most common
+        /// first, counting those --exts would skip; kept ones are marked `*`.
+        /// Helps discover extensions like `.tcc` or `.ipp` worth adding
+        #[arg(
+            long,
+            conflicts_with_all = ["count", "explain", "by_root", "files_from", "archive"]
+        )]
+        ext_histogram: bool,
+    },
+
+    /// Scan for relevant files under given roots, building an import mapping.
+    /// Exits 3 if no relevant files were found, or none of the roots exists,
+    /// and 4 if an output could not be written; other errors exit 1
+    Scan(ScanArgs),
+
+    /// Report include cycles; exits non-zero if any are found
+    Cycles {
+        #[command(flatten)]
+        crawl: CrawlArgs,
+    },
+
+    /// Print files in dependency order, each includee before its includers
+    Toposort {
+        #[command(flatten)]
+        crawl: CrawlArgs,
     },
 
-    /// Scan for relevant files under given roots, building an import mapping
-    Scan {
-        /// One or more root directories to scan
-        #[arg(required = true)]
-        roots: Vec<PathBuf>,
+    /// Print the longest include chain and its length; fails on cycles
+    Depth {
+        #[command(flatten)]
+        crawl: CrawlArgs,
+    },
+
+    /// Print each included file with its number of includers, most included
+    /// first, then node, edge and weakly-connected component counts and the
+    /// average fan-out
+    Stats {
+        #[command(flatten)]
+        crawl: CrawlArgs,
 
-        /// Repeatable ignore patterns (substring match), e.g. --ignore build/ --ignore .git/
-        #[arg(long = "ignore", value_name = "PATTERN", num_args = 0..)]
-        ignores: Vec<String>,
+        #[arg(long, value_enum, default_value_t = StatsFormat::Text)]
+        format: StatsFormat,
+    },
 
-        /// Override relevant file extensions (comma-separated, no dots). Default: c,h,hh,hpp,hxx,inc
-        #[arg(long = "exts", value_name = "CSV")]
-        exts: Option<String>,
+    /// List header files that no scanned file includes; exits non-zero if
+    /// there are more than --allow of them
+    Orphans {
+        #[command(flatten)]
+        crawl: CrawlArgs,
+
+        /// Number of orphans to tolerate before failing
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        allow: usize,
+    },
+
+    /// List headers with exactly one includer, tab-separated from it, as
+    /// candidates for folding into that file
+    SingleUse {
+        #[command(flatten)]
+        crawl: CrawlArgs,
+
+        /// Repeatable pattern, in --ignore syntax, of headers to leave out,
+        /// e.g. public API headers that must stay separate
+        #[arg(long = "exclude", value_name = "PATTERN")]
+        excludes: Vec<String>,
+    },
+
+    /// List headers with neither `#pragma once` nor an `#ifndef`/`#define` guard
+    Guards {
+        #[command(flatten)]
+        crawl: CrawlArgs,
+    },
+
+    /// List file names found in more than one directory, e.g. two `config.h`
+    Duplicates {
+        #[command(flatten)]
+        crawl: CrawlArgs,
+    },
+
+    /// List files that include themselves, with the offending lines;
+    /// exits non-zero if there are any
+    SelfIncludes {
+        #[command(flatten)]
+        crawl: CrawlArgs,
+    },
 
-        /// Follow symlinks during traversal
+    /// List resolved includes whose file is outside every root, e.g. a
+    /// `"../../vendor/x.h"` reaching out of the tree, as `includer:line -> file`
+    Escapes {
+        #[command(flatten)]
+        crawl: CrawlArgs,
+
+        /// Exit non-zero if any include escapes
         #[arg(long)]
-        follow_symlinks: bool,
+        strict: bool,
+    },
+
+    /// Print every distinct include target as written, before resolution,
+    /// with the number of files that include it, most used first. Quoted
+    /// targets print as "x.h", angle ones (with --system) as <x.h>
+    Includes {
+        #[command(flatten)]
+        crawl: CrawlArgs,
+    },
+
+    /// Compare two `scan --format json` outputs and print added (+) and
+    /// removed (-) edges as `includer -> includee`
+    Diff {
+        /// Mapping before the change
+        old: PathBuf,
+
+        /// Mapping after the change
+        new: PathBuf,
+
+        /// Repeatable header (as written in the JSON) that must not gain
+        /// includers; any new edge to it fails the command
+        #[arg(long, value_name = "HEADER")]
+        frozen: Vec<String>,
+    },
+
+    /// Print every file FILE pulls in, directly or transitively
+    Closure {
+        /// File whose includes to follow; a trailing part of its path such
+        /// as `util/math.h` or `math.h` will do if only one node ends in it
+        file: PathBuf,
+
+        #[command(flatten)]
+        crawl: CrawlArgs,
     },
+
+    /// Print every file that includes FILE, directly or transitively
+    Rdeps {
+        /// File whose dependents to find; a trailing part of its path such
+        /// as `util/math.h` or `math.h` will do if only one node ends in it
+        file: PathBuf,
+
+        #[command(flatten)]
+        crawl: CrawlArgs,
+    },
+}
+
+/// Output formats understood by `scan`.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    /// Graphviz DOT
+    Dot,
+    /// `{"includee": ["includer", ...]}` with sorted keys
+    Json,
+    /// One `{"includee", "includer", "line"}` record per include statement
+    Jsonl,
+    /// Mermaid `graph LR` flowchart
+    Mermaid,
+    /// Like jsonl, but each file's records are written as soon as it is
+    /// parsed, in no particular order
+    Ndjson,
+    /// GraphML, for yEd and Gephi
+    Graphml,
+    /// D2 diagram
+    D2,
+    /// PlantUML component diagram
+    Plantuml,
+    /// Plain text: each includee, then its includers indented
+    Text,
+    /// Self-contained HTML page with a collapsible tree
+    Html,
+    /// Adjacency matrix CSV; a cell is 1 when the row file includes the column file
+    Matrix,
+}
+
+/// Where quoted includes are looked up before the -I directories.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum QuoteBase {
+    /// The including file's directory, as compilers do by default
+    File,
+    /// The project root (see --relative-to), for builds that treat quoted
+    /// includes as relative to a virtual source root
+    Project,
+}
+
+/// Which way the DOT arrows point.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Direction {
+    /// Header -> file that includes it: "B is included by A"
+    Includers,
+    /// File -> header it includes: "A depends on B"
+    Deps,
+}
+
+/// What a node of the rendered graph stands for.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Granularity {
+    File,
+    Dir,
+}
+
+/// How `stats` prints its report.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum StatsFormat {
+    Text,
+    Json,
+}
+
+/// Image formats `dot` can render the graph to.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Render {
+    Svg,
+    Png,
+}
+
+impl Render {
+    /// Both the `dot -T` argument and the default output extension.
+    fn name(self) -> &'static str {
+        match self {
+            Render::Svg => "svg",
+            Render::Png => "png",
+        }
+    }
+}
+
+impl Format {
+    /// File extension used for the default output path.
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Dot => "dot",
+            Format::Json => "json",
+            Format::Jsonl => "jsonl",
+            Format::Mermaid => "mmd",
+            Format::Ndjson => "ndjson",
+            Format::Graphml => "graphml",
+            Format::D2 => "d2",
+            Format::Plantuml => "puml",
+            Format::Text => "txt",
+            Format::Html => "html",
+            Format::Matrix => "csv",
+        }
+    }
+}
+
+/// A colon-separated list from environment variable `name`, like `PATH`.
+/// Unset or empty means no list; empty items are dropped.
+fn env_list(name: &str) -> Option<Vec<String>> {
+    let value = std::env::var(name).ok().filter(|v| !v.is_empty())?;
+    Some(
+        value
+            .split(':')
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .collect(),
+    )
+}
+
+/// Failures of `scan` that scripts may want to tell apart from other
+/// errors; see [`ScanError::exit_code`].
+#[derive(Debug)]
+enum ScanError {
+    /// The roots held no relevant files, which usually means a wrong root
+    NoFiles,
+    /// An output could not be created or written
+    Output(PathBuf),
+}
+
+impl ScanError {
+    /// Exit codes: 0 success, 1 any other error, 2 bad usage (from clap),
+    /// 3 no relevant files found, 4 an output could not be written.
+    fn exit_code(&self) -> u8 {
+        match self {
+            ScanError::NoFiles => 3,
+            ScanError::Output(_) => 4,
+        }
+    }
+}
+
+impl std::fmt::Display for ScanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScanError::NoFiles => write!(f, "no relevant files found; check the roots and filters"),
+            ScanError::Output(path) => write!(f, "failed to write {}", path.display()),
+        }
+    }
 }
 
-fn main() -> Result<()> {
+impl std::error::Error for ScanError {}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            let code = match (
+                e.downcast_ref::<ScanError>(),
+                e.downcast_ref::<CrawlError>(),
+            ) {
+                (Some(e), _) => e.exit_code(),
+                // Walking nothing finds no relevant files either
+                (_, Some(CrawlError::RootNotFound(_))) => 3,
+                _ => 1,
+            };
+            ExitCode::from(code)
+        }
+    }
+}
+
+fn run() -> Result<()> {
     let cli = Cli::parse();
+    log::set_logger(&LOGGER).expect("logger is only set once");
+    log::set_max_level(log_level(cli.quiet, cli.verbose));
     match cli.cmd {
         Cmd::List {
-            roots,
-            ignores,
-            exts,
-            follow_symlinks,
-        } => cmd_list(roots, ignores, exts, follow_symlinks),
-        Cmd::Scan {
-            roots,
-            ignores,
-            exts,
-            follow_symlinks,
-        } => cmd_scan(roots, ignores, exts, follow_symlinks),
+            crawl,
+            count,
+            explain,
+            by_root,
+            ext_histogram,
+        } => cmd_list(&crawl, count, explain, by_root, ext_histogram),
+        Cmd::Scan(args) => cmd_scan(&args, cli.quiet),
+        Cmd::Cycles { crawl } => cmd_cycles(&crawl),
+        Cmd::Toposort { crawl } => cmd_toposort(&crawl),
+        Cmd::Depth { crawl } => cmd_depth(&crawl),
+        Cmd::Stats { crawl, format } => cmd_stats(&crawl, format),
+        Cmd::Orphans { crawl, allow } => cmd_orphans(&crawl, allow),
+        Cmd::SingleUse { crawl, excludes } => cmd_single_use(&crawl, &excludes),
+        Cmd::Guards { crawl } => cmd_guards(&crawl),
+        Cmd::Duplicates { crawl } => cmd_duplicates(&crawl),
+        Cmd::SelfIncludes { crawl } => cmd_self_includes(&crawl),
+        Cmd::Escapes { crawl, strict } => cmd_escapes(&crawl, strict),
+        Cmd::Includes { crawl } => cmd_includes(&crawl),
+        Cmd::Diff { old, new, frozen } => cmd_diff(&old, &new, &frozen),
+        Cmd::Closure { file, crawl } => cmd_closure(&file, &crawl),
+        Cmd::Rdeps { file, crawl } => cmd_rdeps(&file, &crawl),
     }
 }
 
-fn cmd_scan(
-    roots: Vec<PathBuf>,
-    ignores: Vec<String>,
-    exts_csv: Option<String>,
-    follow_symlinks: bool,
-) -> Result<()> {
-    let mut mapping = IncludeMapping::new();
-    let found = list_relevant_files(roots, ignores, exts_csv, follow_symlinks)?;
-    for path in found {
-        find_include_lines(&path, &mut mapping)?;
+/// How often --watch polls for changes, and how long the files must stay
+/// unchanged before a rescan, so a burst of saves triggers only one.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+fn cmd_scan(args: &ScanArgs, quiet: bool) -> Result<()> {
+    if !args.watch {
+        return scan_once(args, quiet);
+    }
+    let opts = args.crawl.options()?;
+    loop {
+        // Taken before scanning so edits made during the scan are seen
+        let before = watch_snapshot(&args.crawl, &opts)?;
+        if let Err(e) = scan_once(args, quiet) {
+            error!("{:#}", e);
+        }
+        let mut seen = watch_snapshot(&args.crawl, &opts)?;
+        while seen == before {
+            thread::sleep(WATCH_INTERVAL);
+            seen = watch_snapshot(&args.crawl, &opts)?;
+        }
+        loop {
+            thread::sleep(WATCH_INTERVAL);
+            let now = watch_snapshot(&args.crawl, &opts)?;
+            if now == seen {
+                break;
+            }
+            seen = now;
+        }
+        info!("change detected; rescanning");
+    }
+}
+
+/// Modification time and size of every file scan would read, to compare
+/// between polls. The file list is rebuilt so new and deleted files count.
+fn watch_snapshot(
+    crawl: &CrawlArgs,
+    opts: &CrawlOptions,
+) -> Result<BTreeMap<PathBuf, Option<(SystemTime, u64)>>> {
+    Ok(crawl
+        .files(opts)?
+        .into_iter()
+        .map(|f| {
+            let stat = fs::metadata(&f)
+                .ok()
+                .and_then(|m| Some((m.modified().ok()?, m.len())));
+            (f, stat)
+        })
+        .collect())
+}
+
+/// One scan; unless `quiet`, with a progress bar and a closing summary.
+fn scan_once(args: &ScanArgs, quiet: bool) -> Result<()> {
+    if args.render.is_some() && !args.formats.contains(&Format::Dot) {
+        return Err(anyhow!("--render only works with --format dot"));
     }
+    let targets = args.targets()?;
+    let project_root = args.crawl.project_root();
 
-    let dot = write_dot_left_right(&mapping.inner, PathBuf::from(".").as_path());
-    fs::write("dep-graph.dot", dot)?;
+    // ndjson outputs are opened up front and fed while the files are parsed
+    let (streamed, targets): (Vec<_>, Vec<_>) =
+        targets.into_iter().partition(|(f, _)| *f == Format::Ndjson);
+    if !streamed.is_empty()
+        && (!args.root_files.is_empty()
+            || args.min_fanin.is_some()
+            || args.intra_root_only
+            || args.cross_module_only
+            || args.merge_translation_units
+            || args.crawl.with_rdeps
+            || args.crawl.case_insensitive_paths
+            || args.crawl.dedup_by_realpath
+            || args.granularity != Granularity::File)
+    {
+        return Err(anyhow!(
+            "--format ndjson streams edges as files are parsed, so it cannot be \
+             combined with --root-file, --min-fanin, --intra-root-only, --cross-module-only, \
+             --with-rdeps, --case-insensitive-paths, --dedup-by-realpath, \
+             --merge-translation-units or --granularity dir"
+        ));
+    }
+    let streams = streamed
+        .iter()
+        .map(|(_, path)| Ok((path, Mutex::new(open_output(path)?))))
+        .collect::<Result<Vec<_>>>()?;
+    // Keep stdout-bound output clean when it is piped or redirected
+    let progress = !quiet && std::io::stdout().is_terminal();
+    let total_edges = AtomicUsize::new(0);
+    let mut mapping = args.crawl.build_mapping_with(progress, |_, edges| {
+        let added = edges.inner.values().map(HashSet::len).sum::<usize>();
+        if total_edges.fetch_add(added, Ordering::Relaxed) + added > args.max_edges {
+            return Err(anyhow!(
+                "more than {} edges (--max-edges); narrow the roots, --exts or --ignore, \
+                 or raise --max-edges",
+                args.max_edges
+            ));
+        }
+        if streams.is_empty() || edges.inner.is_empty() {
+            return Ok(());
+        }
+        let records = write_jsonl(edges, &project_root)?;
+        for (path, out) in &streams {
+            let mut out = out.lock().expect("output lock poisoned");
+            out.write_all(records.as_bytes())
+                .and_then(|()| out.flush())
+                .with_context(|| ScanError::Output(path.to_path_buf()))?;
+        }
+        Ok(())
+    })?;
+    // Under --since an empty change set is expected, not a misconfiguration
+    if mapping.metadata.is_empty() && mapping.problems.unreadable == 0 && args.crawl.since.is_none()
+    {
+        return Err(ScanError::NoFiles.into());
+    }
+    if args.count_duplicates {
+        for ((includee, includer), lines) in repeated_includes(&mapping) {
+            let lines = lines.iter().map(usize::to_string).collect::<Vec<_>>();
+            warn!(
+                "{} includes {} {} times (lines {})",
+                rel(includer, &project_root),
+                rel(includee, &project_root),
+                lines.len(),
+                lines.join(", ")
+            );
+        }
+    }
+    // Checked before the render filters below, which would hide offenders
+    let failures = check_limits(args, &mapping, &project_root);
+    if args.intra_root_only {
+        keep_intra_root(&mut mapping, &args.crawl.roots);
+    }
+    if args.cross_module_only {
+        keep_cross_module(&mut mapping, &project_root);
+    }
+    if !args.root_files.is_empty() {
+        let deps = mapping.reverse();
+        let roots = args
+            .root_files
+            .iter()
+            .map(|file| graph_node(&mapping, &deps, file))
+            .collect::<Result<Vec<_>>>()?;
+        prune_unreachable(&mut mapping, &roots);
+    }
+    if args.merge_translation_units {
+        mapping = merge_translation_units(&mapping);
+    }
+    if args.granularity == Granularity::Dir {
+        mapping = by_directory(&mapping, args.keep_self_edges);
+    }
+    if let Some(min) = args.min_fanin {
+        mapping.retain_includees(|_, who| who.len() >= min);
+    }
+
+    let dot_options = args.dot_options();
+    // Render before opening the output so a missing `dot` leaves no empty file
+    let image = args
+        .render
+        .map(|r| {
+            render_dot(
+                &write_dot_left_right(&mapping, &project_root, &dot_options),
+                r,
+            )
+        })
+        .transpose()?;
+
+    for (format, path) in &targets {
+        let mut out = open_output(path)?;
+        let written = match format {
+            Format::Dot => match &image {
+                Some(image) => out.write_all(image),
+                None => write_dot(&mapping, &project_root, &dot_options, &mut out),
+            },
+            Format::Json if args.with_metadata || args.with_mtime => out.write_all(
+                write_json_with_metadata(&mapping, &project_root, args.with_mtime)?.as_bytes(),
+            ),
+            Format::Json => out.write_all(write_json(&mapping, &project_root)?.as_bytes()),
+            Format::Jsonl => out.write_all(write_jsonl(&mapping, &project_root)?.as_bytes()),
+            Format::Mermaid => out.write_all(write_mermaid(&mapping, &project_root).as_bytes()),
+            Format::Graphml => out.write_all(write_graphml(&mapping, &project_root).as_bytes()),
+            Format::D2 => out.write_all(write_d2(&mapping, &project_root).as_bytes()),
+            Format::Plantuml => out.write_all(write_plantuml(&mapping, &project_root).as_bytes()),
+            Format::Text => write!(out, "{}", mapping.display_relative(&project_root)),
+            Format::Html => out.write_all(write_html(&mapping, &project_root)?.as_bytes()),
+            Format::Matrix => write_matrix(&mapping, &project_root, &mut out),
+            Format::Ndjson => unreachable!("streamed while parsing"),
+        };
+        written
+            .and_then(|()| out.flush())
+            .with_context(|| ScanError::Output(path.clone()))?;
+    }
+    if let Some(manifest) = &args.manifest {
+        let outputs = streamed
+            .iter()
+            .chain(&targets)
+            .map(|(format, path)| {
+                let name = match (format, args.render) {
+                    (Format::Dot, Some(render)) => render.name().to_string(),
+                    _ => format
+                        .to_possible_value()
+                        .expect("no skipped formats")
+                        .get_name()
+                        .to_string(),
+                };
+                serde_json::json!({ "path": path, "format": name })
+            })
+            .collect::<Vec<_>>();
+        let text = serde_json::to_string_pretty(&serde_json::json!({ "outputs": outputs }))?;
+        let mut out = open_output(manifest)?;
+        writeln!(out, "{}", text)
+            .and_then(|()| out.flush())
+            .with_context(|| ScanError::Output(manifest.clone()))?;
+    }
+
+    // The summary is the scan's own result rather than a diagnostic, so it
+    // is shown by default and only -q hides it, whatever the log level
+    if !quiet {
+        let includers = mapping.inner.values().flatten().collect::<HashSet<_>>();
+        let edges = mapping.inner.values().map(HashSet::len).sum::<usize>();
+        eprintln!(
+            "{} includee(s), {} includer(s), {} edge(s)",
+            mapping.inner.len(),
+            includers.len(),
+            edges
+        );
+    }
+
+    if args.strict && !failures.is_empty() {
+        return Err(anyhow!(failures.join("; ")));
+    }
     Ok(())
 }
 
-fn find_include_lines(path: &Path, mapping: &mut IncludeMapping) -> Result<()> {
-    let file =
-        File::open(path).with_context(|| format!("failed to open file {}", path.display()))?;
-    let reader = BufReader::new(file);
+/// Run the --max-includes and --rule checks, warning about each offender.
+/// Returns one summary per failed check, for --strict.
+fn check_limits(args: &ScanArgs, mapping: &IncludeMapping, project_root: &Path) -> Vec<String> {
+    let mut failures = Vec::new();
+    if let Some(max) = args.max_includes {
+        let over = over_include_limit(mapping, max);
+        for (file, count) in &over {
+            warn!(
+                "{} has {} includes (max {})",
+                rel(file, project_root),
+                count,
+                max
+            );
+        }
+        if !over.is_empty() {
+            failures.push(format!(
+                "{} file(s) exceed --max-includes {}",
+                over.len(),
+                max
+            ));
+        }
+    }
+    if !args.rules.is_empty() {
+        let broken = rule_violations(mapping, &args.rules, project_root);
+        for (includer, header) in &broken {
+            warn!("{} includes {}, breaking --rule", includer, header);
+        }
+        if !broken.is_empty() {
+            failures.push(format!("{} --rule violation(s)", broken.len()));
+        }
+    }
+    failures
+}
 
-    for line in reader.lines() {
-        let line = line?;
-        let trimmed = line.trim_start();
-        if trimmed.starts_with("#include") {
-            let parsed = parse_include_path(&trimmed);
-            if let Some(include) = parsed {
-                mapping.insert(include, PathBuf::from(path));
+/// (includer, header) pairs, as relative paths and sorted, where the
+/// includer is outside every prefix its header's rule allows.
+fn rule_violations(
+    mapping: &IncludeMapping,
+    rules: &[Rule],
+    project_root: &Path,
+) -> BTreeSet<(String, String)> {
+    let mut broken = BTreeSet::new();
+    for (includee, who) in &mapping.inner {
+        let header = rel(includee, project_root);
+        for rule in rules.iter().filter(|r| r.header == Path::new(&header)) {
+            for includer in who {
+                let includer = rel(includer, project_root);
+                if !rule
+                    .allowed
+                    .iter()
+                    .any(|p| Path::new(&includer).starts_with(p))
+                {
+                    broken.insert((includer, header.clone()));
+                }
             }
         }
     }
+    broken
+}
+
+/// Canonical paths of the files `git diff --name-only REF` reports, run in
+/// the current directory. Deleted files are left out.
+fn changed_since(git_ref: &str) -> Result<HashSet<PathBuf>> {
+    let git = |args: &[&str]| -> Result<String> {
+        let out = Command::new("git")
+            .args(args)
+            .output()
+            .context("failed to run git")?;
+        if !out.status.success() {
+            return Err(anyhow!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&out.stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+    };
+    // Names are relative to the top of the work tree, not the current directory
+    let top = PathBuf::from(git(&["rev-parse", "--show-toplevel"])?.trim_end());
+    let names = git(&["diff", "--name-only", git_ref, "--"])?;
+    Ok(names
+        .lines()
+        .filter_map(|name| top.join(name).canonicalize().ok())
+        .collect())
+}
+
+/// Edges included on more than one line, sorted by includer then includee.
+fn repeated_includes(mapping: &IncludeMapping) -> Vec<((&PathBuf, &PathBuf), &BTreeSet<usize>)> {
+    let mut repeated = mapping
+        .lines
+        .iter()
+        .filter(|(_, lines)| lines.len() > 1)
+        .map(|((includee, includer), lines)| ((includee, includer), lines))
+        .collect::<Vec<_>>();
+    repeated.sort_by_key(|((includee, includer), _)| (*includer, *includee));
+    repeated
+}
+
+/// Includers with more than `max` include lines, sorted by path.
+fn over_include_limit(mapping: &IncludeMapping, max: usize) -> BTreeMap<&PathBuf, usize> {
+    let mut counts: BTreeMap<&PathBuf, usize> = BTreeMap::new();
+    for ((_, includer), lines) in &mapping.lines {
+        *counts.entry(includer).or_default() += lines.len();
+    }
+    counts.retain(|_, count| *count > max);
+    counts
+}
+
+/// Run Graphviz `dot` on `source` and return the rendered image.
+fn render_dot(source: &str, render: Render) -> Result<Vec<u8>> {
+    let mut child = Command::new("dot")
+        .arg(format!("-T{}", render.name()))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => anyhow!(
+                "`dot` not found on PATH; install Graphviz (https://graphviz.org) to use --render"
+            ),
+            _ => anyhow!(e).context("failed to run dot"),
+        })?;
+
+    // Feed stdin from a thread so a large graph can't deadlock on full pipes
+    let mut stdin = child.stdin.take().context("dot stdin unavailable")?;
+    let source = source.to_string();
+    let feeder = std::thread::spawn(move || stdin.write_all(source.as_bytes()));
+    let output = child.wait_with_output().context("failed to run dot")?;
+    feeder
+        .join()
+        .map_err(|_| anyhow!("dot input thread panicked"))?
+        .context("failed to write to dot")?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "dot exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(output.stdout)
+}
+
+/// Open `path` for writing, creating parent directories as needed.
+/// A path of `-` means stdout.
+fn open_output(path: &Path) -> Result<Box<dyn std::io::Write + Send>> {
+    if path == Path::new("-") {
+        return Ok(Box::new(std::io::stdout()));
+    }
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create directory {}", parent.display()))
+            .with_context(|| ScanError::Output(path.to_path_buf()))?;
+    }
+    let file = fs::File::create(path).with_context(|| ScanError::Output(path.to_path_buf()))?;
+    Ok(Box::new(BufWriter::new(file)))
+}
+
+/// Prints every include cycle as a chain like `a.h -> b.h -> a.h`.
+fn cmd_cycles(crawl: &CrawlArgs) -> Result<()> {
+    let mapping = crawl.build_graph()?;
+    let project_root = crawl.project_root();
+
+    let cycles = find_cycles(&dependency_graph(&mapping));
+    for cycle in &cycles {
+        println!("{}", format_chain(cycle, &project_root));
+    }
+
+    if !cycles.is_empty() {
+        return Err(anyhow!("found {} include cycle(s)", cycles.len()));
+    }
     Ok(())
 }
 
-/// Parses an `#include` line like `#include "../thingy/thing.c"`
-/// and returns `Some(PathBuf)` for quoted includes.
-/// Returns `None` for angle-bracket includes or invalid syntax.
-fn parse_include_path(line: &str) -> Option<PathBuf> {
-    // Slice off "#include"
-    let rest = line["#include".len()..].trim_start();
+/// Prints files in build order, each includee before its includers.
+fn cmd_toposort(crawl: &CrawlArgs) -> Result<()> {
+    let mapping = crawl.build_graph()?;
+    let project_root = crawl.project_root();
 
-    if rest.starts_with('<') {
-        // System include — ignore
-        return None;
+    match toposort(&dependency_graph(&mapping)) {
+        Ok(order) => {
+            for p in order {
+                println!("{}", rel(&p, &project_root));
+            }
+            Ok(())
+        }
+        Err(cycles) => {
+            let chains = cycles
+                .iter()
+                .map(|c| format_chain(c, &project_root))
+                .collect::<Vec<_>>();
+            Err(anyhow!(
+                "include graph has no topological order; cycle(s):\n  {}",
+                chains.join("\n  ")
+            ))
+        }
     }
+}
+
+/// The include "critical path": the longest chain of nested includes.
+fn cmd_depth(crawl: &CrawlArgs) -> Result<()> {
+    let mapping = crawl.build_graph()?;
+    let project_root = crawl.project_root();
 
-    if let Some(start) = rest.find('"') {
-        let after_start = &rest[start + 1..];
-        if let Some(end) = after_start.find('"') {
-            let path_str = &after_start[..end];
-            // Normalize path separators if needed
-            let path = PathBuf::from(path_str);
-            return Some(path);
+    match longest_chain(&dependency_graph(&mapping)) {
+        Ok(chain) => {
+            println!("depth {}", chain.len().saturating_sub(1));
+            println!("{}", format_chain(&chain, &project_root));
+            Ok(())
+        }
+        Err(cycles) => {
+            let chains = cycles
+                .iter()
+                .map(|c| format_chain(c, &project_root))
+                .collect::<Vec<_>>();
+            Err(anyhow!(
+                "include graph has cycles, so no longest chain; cycle(s):\n  {}",
+                chains.join("\n  ")
+            ))
         }
     }
+}
 
-    None
+/// `a.h -> b.h -> a.h`, with paths relative to `root`.
+fn format_chain(chain: &[PathBuf], root: &Path) -> String {
+    chain
+        .iter()
+        .map(|p| rel(p, root))
+        .collect::<Vec<_>>()
+        .join(" -> ")
 }
 
-///Lists all the relevant files found under a given root directory
-fn cmd_list(
-    roots: Vec<PathBuf>,
-    ignores: Vec<String>,
-    exts_csv: Option<String>,
-    follow_symlinks: bool,
-) -> Result<()> {
-    let mut found = list_relevant_files(roots, ignores, exts_csv, follow_symlinks)?;
+/// Fan-in report: includees sorted by includer count, descending,
+/// with ties broken alphabetically.
+fn cmd_stats(crawl: &CrawlArgs, format: StatsFormat) -> Result<()> {
+    let mapping = crawl.build_mapping()?;
+    let project_root = crawl.project_root();
 
-    found.sort();
-    found.dedup();
-    for p in found {
-        println!("{}", p.display());
-    }
+    let mut rows = mapping
+        .inner
+        .iter()
+        .map(|(includee, who)| (who.len(), rel(includee, &project_root)))
+        .collect::<Vec<_>>();
+    rows.sort_by(|(ca, a), (cb, b)| cb.cmp(ca).then_with(|| a.cmp(b)));
+
+    let graph = dependency_graph(&mapping);
+    let nodes = graph.len();
+    let edges = graph.values().map(BTreeSet::len).sum::<usize>();
+    let components = weakly_connected(&graph).len();
+    // Mean number of includes per node, leaves included
+    let avg_fanout = if nodes == 0 {
+        0.0
+    } else {
+        edges as f64 / nodes as f64
+    };
 
+    match format {
+        StatsFormat::Text => {
+            for (count, path) in rows {
+                println!("{:>6}  {}", count, path);
+            }
+            println!();
+            println!("nodes:       {}", nodes);
+            println!("edges:       {}", edges);
+            println!("components:  {}", components);
+            println!("avg fan-out: {:.2}", avg_fanout);
+        }
+        StatsFormat::Json => {
+            let includers = rows
+                .iter()
+                .map(|(count, path)| serde_json::json!({ "path": path, "includers": count }))
+                .collect::<Vec<_>>();
+            let report = serde_json::json!({
+                "nodes": nodes,
+                "edges": edges,
+                "components": components,
+                "avg_fanout": avg_fanout,
+                "includees": includers,
+            });
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+    }
     Ok(())
 }
 
-fn list_relevant_files(
-    roots: Vec<PathBuf>,
-    ignores: Vec<String>,
-    exts_csv: Option<String>,
-    follow_symlinks: bool,
-) -> Result<Vec<PathBuf>> {
-    if roots.is_empty() {
-        return Err(anyhow!("provide at least one root directory"));
+/// Headers found under the roots that never appear as an includee.
+/// Source files are skipped since nothing is expected to include them.
+fn cmd_orphans(crawl: &CrawlArgs, allow: usize) -> Result<()> {
+    let mapping = crawl.build_mapping()?;
+    let project_root = crawl.project_root();
+
+    // Every parsed file has metadata, under its folded path if it was merged
+    let orphans = mapping
+        .metadata
+        .keys()
+        .filter(|p| !is_source(p) && !mapping.inner.contains_key(*p))
+        .collect::<BTreeSet<_>>();
+    for p in &orphans {
+        println!("{}", rel(p, &project_root));
+    }
+
+    if orphans.len() > allow {
+        return Err(anyhow!(
+            "found {} orphan header(s), {} allowed",
+            orphans.len(),
+            allow
+        ));
     }
+    Ok(())
+}
 
-    let ignored = ignores.into_iter().collect::<BTreeSet<_>>();
-    let exts = parse_exts(exts_csv);
+/// Headers included by a single file, sorted, with that file.
+fn cmd_single_use(crawl: &CrawlArgs, excludes: &[String]) -> Result<()> {
+    let mapping = crawl.build_mapping()?;
+    let project_root = crawl.project_root();
 
-    let mut found: Vec<PathBuf> = Vec::new();
+    let mut single = mapping
+        .inner
+        .iter()
+        .filter(|(p, who)| {
+            who.len() == 1
+                && !is_source(p)
+                && !mapping.system.contains(*p)
+                && !mapping.unresolved.contains(*p)
+        })
+        .filter(|(p, _)| {
+            let shown = rel(p, &project_root);
+            !excludes
+                .iter()
+                .any(|pat| ignore_matches(Path::new(&shown), pat))
+        })
+        .map(|(p, who)| (p, who.iter().next().expect("one includer")))
+        .collect::<Vec<_>>();
+    single.sort();
+    for (header, includer) in single {
+        println!(
+            "{}\t{}",
+            rel(header, &project_root),
+            rel(includer, &project_root)
+        );
+    }
+    Ok(())
+}
+
+/// Headers found under the roots that lack an include guard.
+fn cmd_guards(crawl: &CrawlArgs) -> Result<()> {
+    let opts = crawl.options()?;
+    let mut found = crawl.files(&opts)?;
+    found.sort();
+    found.dedup();
+    let project_root = crawl.project_root();
 
-    for root in roots {
-        let root = canonicalize_lenient(&root);
-        if !root.exists() {
-            eprintln!("warn: skipping non-existent root {}", root.display());
-            continue;
+    for p in found.iter().filter(|p| !is_source(p)) {
+        if !has_include_guard(p)? {
+            println!("{}", rel(p, &project_root));
         }
-        let walker = if follow_symlinks {
-            WalkDir::new(&root).follow_links(true)
-        } else {
-            WalkDir::new(&root)
-        };
+    }
+    Ok(())
+}
 
-        for entry in walker.into_iter().filter_map(|e| e.ok()) {
-            let path = entry.path();
+/// Found files grouped by basename; prints each name that occurs more
+/// than once, followed by its paths indented.
+fn cmd_duplicates(crawl: &CrawlArgs) -> Result<()> {
+    let opts = crawl.options()?;
+    let found = crawl.files(&opts)?;
+    let project_root = crawl.project_root();
 
-            // skip directories
-            if entry.file_type().is_dir() {
-                continue;
-            }
+    let mut by_name: BTreeMap<String, BTreeSet<&PathBuf>> = BTreeMap::new();
+    for p in &found {
+        if let Some(name) = p.file_name() {
+            by_name
+                .entry(name.to_string_lossy().to_string())
+                .or_default()
+                .insert(p);
+        }
+    }
+    for (name, paths) in by_name.iter().filter(|(_, paths)| paths.len() > 1) {
+        println!("{}", name);
+        for p in paths {
+            println!("  {}", rel(p, &project_root));
+        }
+    }
+    Ok(())
+}
 
-            // apply simple substring ignores
-            let s = path.to_string_lossy();
-            if ignored.iter().any(|pat| s.contains(pat)) {
+/// Raw include targets with the number of distinct files using each.
+fn cmd_includes(crawl: &CrawlArgs) -> Result<()> {
+    let opts = crawl.options()?;
+    let mut users: BTreeMap<String, BTreeSet<PathBuf>> = BTreeMap::new();
+    for file in crawl.files(&opts)? {
+        let parsed = match parse_file_within(&file, &opts.parse, opts.read_timeout) {
+            Ok(parsed) => parsed,
+            Err(e) if opts.fail_fast => return Err(e.into()),
+            Err(e) => {
+                warn!("{:#}; skipping it", anyhow::Error::from(e));
                 continue;
             }
-
-            // filter by extension set
-            if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
-                if !exts.contains(ext) {
-                    continue;
-                }
-            } else {
-                // no extension → skip
+        };
+        for (_, include) in parsed.includes {
+            if include.system && !opts.parse.system {
                 continue;
             }
+            let target = if include.system {
+                format!("<{}>", include.path.display())
+            } else {
+                format!("\"{}\"", include.path.display())
+            };
+            users.entry(target).or_default().insert(file.clone());
+        }
+    }
 
-            found.push(canonicalize_lenient(path));
+    let mut rows = users
+        .into_iter()
+        .map(|(target, files)| (files.len(), target))
+        .collect::<Vec<_>>();
+    rows.sort_by(|(ca, a), (cb, b)| cb.cmp(ca).then_with(|| a.cmp(b)));
+    for (count, target) in rows {
+        println!("{:>6}  {}", count, target);
+    }
+    Ok(())
+}
+
+/// Files that include themselves, as `path:line` for each offending include.
+fn cmd_self_includes(crawl: &CrawlArgs) -> Result<()> {
+    let opts = crawl.options()?;
+    let mapping = map_files(&crawl.files(&opts)?, &opts)?;
+    report_problems(&mapping);
+    let project_root = crawl.project_root();
+
+    let found = mapping.self_includes();
+    for p in &found {
+        let lines = mapping.lines.get(&((*p).clone(), (*p).clone()));
+        for line in lines.into_iter().flatten() {
+            println!("{}:{}", rel(p, &project_root), line);
         }
     }
-    Ok(found)
+    if !found.is_empty() {
+        return Err(anyhow!("found {} self-including file(s)", found.len()));
+    }
+    Ok(())
 }
 
-fn parse_exts(exts_csv: Option<String>) -> BTreeSet<String> {
-    let default = "c,h,hh,hpp,hxx,inc";
-    let raw = exts_csv.as_deref().unwrap_or(default);
-    raw.split(',')
-        .filter(|s| !s.trim().is_empty())
-        .map(|s| s.trim().trim_start_matches('.').to_string())
-        .collect()
+/// Resolved includes pointing outside all of the roots.
+fn cmd_escapes(crawl: &CrawlArgs, strict: bool) -> Result<()> {
+    if crawl.roots.is_empty() {
+        return Err(anyhow!("escapes needs root directories to check against"));
+    }
+    let mapping = crawl.build_mapping()?;
+    let project_root = crawl.project_root();
+    let roots = crawl
+        .roots
+        .iter()
+        .map(|r| canonicalize_lenient(r))
+        .collect::<Vec<_>>();
+
+    let mut escapes = mapping
+        .lines
+        .iter()
+        .filter(|((includee, _), _)| {
+            !mapping.system.contains(includee)
+                && !mapping.unresolved.contains(includee)
+                && !roots.iter().any(|r| includee.starts_with(r))
+        })
+        .flat_map(|((includee, includer), lines)| {
+            lines.iter().map(move |line| (includer, *line, includee))
+        })
+        .collect::<Vec<_>>();
+    escapes.sort();
+    for (includer, line, includee) in &escapes {
+        println!(
+            "{}:{} -> {}",
+            rel(includer, &project_root),
+            line,
+            rel(includee, &project_root)
+        );
+    }
+    if strict && !escapes.is_empty() {
+        return Err(anyhow!(
+            "found {} include(s) escaping the roots",
+            escapes.len()
+        ));
+    }
+    Ok(())
 }
 
-fn canonicalize_lenient(p: &Path) -> PathBuf {
-    p.canonicalize().unwrap_or_else(|_| p.to_path_buf())
+/// Edge-level diff of two JSON mappings, so a reviewer sees which
+/// includes a change added or removed.
+fn cmd_diff(old: &Path, new: &Path, frozen: &[String]) -> Result<()> {
+    let load = |path: &Path| -> Result<BTreeSet<(String, String)>> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let mapping =
+            read_json(&text).with_context(|| format!("invalid mapping {}", path.display()))?;
+        Ok(mapping
+            .into_iter()
+            .flat_map(|(includee, who)| who.into_iter().map(move |w| (w, includee.clone())))
+            .collect())
+    };
+    let (old, new) = (load(old)?, load(new)?);
+
+    for (includer, includee) in old.difference(&new) {
+        println!("- {} -> {}", includer, includee);
+    }
+    let added = new.difference(&old).collect::<Vec<_>>();
+    for (includer, includee) in &added {
+        println!("+ {} -> {}", includer, includee);
+    }
+
+    let frozen_hits = added
+        .iter()
+        .filter(|(_, includee)| frozen.contains(includee))
+        .count();
+    if frozen_hits > 0 {
+        return Err(anyhow!(
+            "{} new include(s) of frozen header(s)",
+            frozen_hits
+        ));
+    }
+    Ok(())
 }
 
-/// Render mapping (includee -> {includers}) with includees on the LEFT and includers on the RIGHT.
-pub fn write_dot_left_right(
-    mapping: &HashMap<PathBuf, HashSet<PathBuf>>,
-    project_root: &Path,
-) -> String {
-    fn esc(s: &str) -> String {
-        s.replace('\\', "\\\\").replace('"', "\\\"")
-    }
-    fn rel<'a>(p: &'a Path, root: &Path) -> String {
-        match p.strip_prefix(root) {
-            Ok(r) => r.to_string_lossy().to_string(),
-            Err(_) => p.to_string_lossy().to_string(),
-        }
-    }
-    fn classify(p: &Path) -> (&'static str, &'static str) {
-        match p.extension().and_then(|e| e.to_str()) {
-            Some("c") => ("ellipse", "#e8f0fe"), // sources
-            _ => ("box", "#fff7e6"),             // headers/others
-        }
-    }
-
-    // Collect sets
-    let mut includees: HashSet<PathBuf> = HashSet::new();
-    let mut includers: HashSet<PathBuf> = HashSet::new();
-    for (inc, who) in mapping {
-        includees.insert(inc.clone());
-        includers.extend(who.iter().cloned());
-    }
-
-    let mut out = String::new();
-    out.push_str("digraph Includes {\n");
-    out.push_str("  rankdir=LR;\n");
-    out.push_str("  graph [splines=true, concentrate=true];\n");
-    out.push_str("  node  [fontname=\"Helvetica\", fontsize=10, style=filled];\n");
-    out.push_str("  edge  [arrowhead=vee];\n");
-
-    // Left column: includees
-    out.push_str("  { rank=source;\n");
-    for n in &includees {
-        let (shape, fill) = classify(n);
-        let label = esc(&rel(n, project_root));
-        let _ = writeln!(
-            out,
-            "    \"{}\" [shape={}, fillcolor=\"{}\"];",
-            label, shape, fill
-        );
+/// Transitive include closure of `file`, sorted.
+fn cmd_closure(file: &Path, crawl: &CrawlArgs) -> Result<()> {
+    let mapping = crawl.build_mapping()?;
+    let project_root = crawl.project_root();
+
+    let deps = mapping.reverse();
+    let file = graph_node(&mapping, &deps, file)?;
+    let closure = reachable(&file, |p| deps.get(p).into_iter().flatten().collect());
+    for p in closure {
+        println!("{}", rel(&p, &project_root));
     }
-    out.push_str("  }\n");
-
-    // Right column: includers
-    out.push_str("  { rank=sink;\n");
-    for n in &includers {
-        let (shape, fill) = classify(n);
-        let label = esc(&rel(n, project_root));
-        let _ = writeln!(
-            out,
-            "    \"{}\" [shape={}, fillcolor=\"{}\"];",
-            label, shape, fill
-        );
+    Ok(())
+}
+
+/// Every file that transitively includes `file`, sorted; these are the
+/// files whose compilation a change to `file` could affect.
+fn cmd_rdeps(file: &Path, crawl: &CrawlArgs) -> Result<()> {
+    let mapping = crawl.build_mapping()?;
+    let project_root = crawl.project_root();
+
+    let file = graph_node(&mapping, &mapping.reverse(), file)?;
+    let dependents = reachable(&file, |p| {
+        mapping.inner.get(p).into_iter().flatten().collect()
+    });
+    for p in dependents {
+        println!("{}", rel(&p, &project_root));
+    }
+    Ok(())
+}
+
+/// Canonicalize a user-supplied FILE and check it is a node of the graph,
+/// either as an includee or as an includer. Like an abbreviated git
+/// revision, FILE may also be the last components of exactly one node's
+/// path, e.g. its basename.
+fn graph_node(
+    mapping: &IncludeMapping,
+    deps: &HashMap<PathBuf, HashSet<PathBuf>>,
+    file: &Path,
+) -> Result<PathBuf> {
+    let canonical = canonicalize_lenient(file);
+    if mapping.inner.contains_key(&canonical) || deps.contains_key(&canonical) {
+        return Ok(canonical);
     }
-    out.push_str("  }\n");
+    let candidates = mapping
+        .inner
+        .keys()
+        .chain(deps.keys())
+        .filter(|p| p.ends_with(file))
+        .collect::<BTreeSet<_>>();
+    match candidates.len() {
+        0 => Err(anyhow!(
+            "{} is not part of the include graph",
+            canonical.display()
+        )),
+        1 => Ok(candidates
+            .into_iter()
+            .next()
+            .expect("one candidate")
+            .clone()),
+        n => {
+            let listed = candidates
+                .iter()
+                .map(|p| format!("\n  {}", p.display()))
+                .collect::<String>();
+            Err(anyhow!(
+                "{} matches {} files; give more of its path:{}",
+                file.display(),
+                n,
+                listed
+            ))
+        }
+    }
+}
 
-    // Edges: includee -> includer (so left → right)
-    for (includee, who) in mapping {
-        let from = esc(&rel(includee, project_root));
-        for inc in who {
-            let to = esc(&rel(inc, project_root));
-            let _ = writeln!(out, "  \"{}\" -> \"{}\";", from, to);
+///Lists all the relevant files found under a given root directory.
+/// With `system`, also lists the angle-bracket headers those files include.
+/// With `count`, prints just the number of files; with `explain`, every
+/// file considered, tagged with the filter's verdict.
+fn cmd_list(
+    crawl: &CrawlArgs,
+    count: bool,
+    explain: bool,
+    by_root: bool,
+    ext_histogram: bool,
+) -> Result<()> {
+    let opts = crawl.options()?;
+    if ext_histogram {
+        // Ignore patterns still apply; only the extension filter is bypassed
+        let mut counts: BTreeMap<String, (usize, bool)> = BTreeMap::new();
+        for (path, verdict) in explain_files(&crawl.roots, &opts.walk)? {
+            let ext = match verdict {
+                Verdict::Ignored(_) => continue,
+                Verdict::NoExt => "(none)".to_string(),
+                _ => path
+                    .extension()
+                    .map(|e| format!(".{}", e.to_string_lossy().to_ascii_lowercase()))
+                    .unwrap_or_default(),
+            };
+            let entry = counts.entry(ext).or_default();
+            entry.0 += 1;
+            entry.1 |= verdict == Verdict::Kept;
+        }
+        let mut counts = counts.into_iter().collect::<Vec<_>>();
+        counts.sort_by(|a, b| b.1.0.cmp(&a.1.0).then_with(|| a.0.cmp(&b.0)));
+        for (ext, (n, kept)) in counts {
+            println!("{:>6}  {}{}", n, ext, if kept { " *" } else { "" });
         }
+        return Ok(());
     }
+    if explain {
+        for (path, verdict) in explain_files(&crawl.roots, &opts.walk)? {
+            println!("{}\t{}", verdict, path.display());
+        }
+        return Ok(());
+    }
+    if by_root {
+        for (root, mut files) in list_files_by_root(&crawl.roots, &opts.walk)? {
+            files.sort();
+            if count {
+                println!("{}: {}", root.display(), files.len());
+                continue;
+            }
+            println!("{}:", root.display());
+            for p in &files {
+                println!("  {}", rel(p, &root));
+            }
+        }
+        return Ok(());
+    }
+    let mut found = crawl.files(&opts)?;
 
-    out.push_str("}\n");
-    out
+    found.sort();
+    found.dedup();
+    if count {
+        println!("{}", found.len());
+        return Ok(());
+    }
+    for p in &found {
+        println!("{}", p.display());
+    }
+
+    if crawl.system {
+        let mapping = map_files(&found, &opts)?;
+        report_problems(&mapping);
+        let headers = mapping.system.iter().collect::<BTreeSet<_>>();
+        for h in headers {
+            println!("<{}>", h.display());
+        }
+    }
+
+    Ok(())
 }
@@ -0,0 +1,608 @@
+//! Serializers that turn an [`IncludeMapping`] into graph description formats.
+
+use crate::error::{CrawlError, Result};
+use crate::graph::top_level_dir;
+use crate::{IncludeMapping, is_source, rel};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::Write as _;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The mapping as a sorted includee -> {includers} map of paths relative to
+/// `project_root`, the shape every JSON output uses.
+fn relative_map(
+    mapping: &IncludeMapping,
+    project_root: &Path,
+) -> BTreeMap<String, BTreeSet<String>> {
+    let mut out: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for (includee, who) in &mapping.inner {
+        out.entry(rel(includee, project_root))
+            .or_default()
+            .extend(who.iter().map(|p| rel(p, project_root)));
+    }
+    out
+}
+
+/// Serialize mapping (includee -> [includers]) as JSON.
+/// Keys and includer lists are sorted so the output is stable across runs.
+pub fn write_json(mapping: &IncludeMapping, project_root: &Path) -> Result<String> {
+    let mut json = serde_json::to_string_pretty(&relative_map(mapping, project_root))
+        .map_err(CrawlError::Serialize)?;
+    json.push('\n');
+    Ok(json)
+}
+
+/// Read back the output of [`write_json`] or [`write_json_with_metadata`]
+/// as a sorted includee -> {includers} map of the paths as written.
+pub fn read_json(text: &str) -> Result<BTreeMap<String, BTreeSet<String>>> {
+    let invalid = |e: serde_json::Error| CrawlError::Parse {
+        what: "JSON",
+        path: None,
+        reason: e.to_string(),
+    };
+    let value: serde_json::Value = serde_json::from_str(text).map_err(invalid)?;
+    let with_metadata = value.get("includes").is_some_and(|v| v.is_object())
+        && value.get("nodes").is_some_and(|v| v.is_object());
+    let includes = if with_metadata {
+        value["includes"].clone()
+    } else {
+        value
+    };
+    serde_json::from_value(includes).map_err(invalid)
+}
+
+/// [`write_json`] plus per-file metadata:
+/// `{"includes": {includee: [includers]}, "nodes": {path: {"size", "lines"}}}`.
+/// With `with_mtime`, nodes also get an RFC 3339 UTC `"mtime"`.
+/// Only graph nodes that were parsed have metadata; system and unresolved
+/// includes, and headers outside the roots, are absent from `nodes`.
+pub fn write_json_with_metadata(
+    mapping: &IncludeMapping,
+    project_root: &Path,
+    with_mtime: bool,
+) -> Result<String> {
+    let includes = relative_map(mapping, project_root);
+    let in_graph = mapping
+        .inner
+        .iter()
+        .flat_map(|(includee, who)| std::iter::once(includee).chain(who))
+        .collect::<HashSet<_>>();
+    let nodes = mapping
+        .metadata
+        .iter()
+        .filter(|(p, _)| in_graph.contains(p))
+        .map(|(p, meta)| {
+            let mut node = serde_json::json!({ "size": meta.size, "lines": meta.lines });
+            if with_mtime {
+                node["mtime"] = meta.mtime.map(rfc3339).into();
+            }
+            (rel(p, project_root), node)
+        })
+        .collect::<BTreeMap<_, _>>();
+    let out = serde_json::json!({ "includes": includes, "nodes": nodes });
+    let mut json = serde_json::to_string_pretty(&out).map_err(CrawlError::Serialize)?;
+    json.push('\n');
+    Ok(json)
+}
+
+/// Format `t` as an RFC 3339 UTC timestamp like `2024-05-01T12:34:56Z`,
+/// to the second. Times before the epoch clamp to it.
+fn rfc3339(t: SystemTime) -> String {
+    let secs = t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
+
+/// One JSON object per line for every edge: `{"includee", "includer", "line"}`.
+/// An edge included on several lines yields one record per line; records
+/// are sorted so the output is stable.
+pub fn write_jsonl(mapping: &IncludeMapping, project_root: &Path) -> Result<String> {
+    let mut records: Vec<(String, String, Option<usize>)> = Vec::new();
+    for (includee, who) in &mapping.inner {
+        for includer in who {
+            let (from, to) = (rel(includee, project_root), rel(includer, project_root));
+            match mapping.lines.get(&(includee.clone(), includer.clone())) {
+                Some(lines) => {
+                    records.extend(lines.iter().map(|l| (from.clone(), to.clone(), Some(*l))))
+                }
+                None => records.push((from, to, None)),
+            }
+        }
+    }
+    records.sort();
+
+    let mut out = String::new();
+    for (includee, includer, line) in records {
+        let record = serde_json::json!({
+            "includee": includee,
+            "includer": includer,
+            "line": line,
+        });
+        out.push_str(&serde_json::to_string(&record).map_err(CrawlError::Serialize)?);
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// DOT shape and fill color for a node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeStyle {
+    pub shape: String,
+    pub color: String,
+}
+
+/// Knobs for `write_dot_left_right`.
+#[derive(Debug, Clone, Default)]
+pub struct DotOptions {
+    /// Per-extension (lowercase, no dot) style overrides, consulted before the defaults
+    pub node_styles: HashMap<String, NodeStyle>,
+    /// Group nodes into `subgraph cluster_*` blocks by first path component
+    /// instead of the includee/includer columns
+    pub cluster_by_dir: bool,
+    /// Draw edges includer -> includee ("A depends on B" as A -> B), with
+    /// includers on the left, instead of the default includee -> includer
+    pub deps_direction: bool,
+    /// Append each node's number of includes to its label, e.g. `foo.c (12)`
+    pub label_with_count: bool,
+    /// Label edges included on more than one line with that line count
+    pub edge_weights: bool,
+}
+
+/// DOT shape and fill color of `p` when no --node-style override applies.
+fn default_style(mapping: &IncludeMapping, p: &Path) -> (&'static str, &'static str) {
+    if mapping.system.contains(p) {
+        return ("box", "#e6f4ea"); // system headers
+    }
+    if is_source(p) {
+        ("ellipse", "#e8f0fe") // sources
+    } else {
+        ("box", "#fff7e6") // headers/others
+    }
+}
+
+/// Render mapping (includee -> {includers}) with includees on the LEFT and includers on the RIGHT,
+/// or the mirror image with [`DotOptions::deps_direction`].
+/// System includes get their own fill color; unresolved includes a dashed border.
+pub fn write_dot_left_right(
+    mapping: &IncludeMapping,
+    project_root: &Path,
+    opts: &DotOptions,
+) -> String {
+    let mut out = Vec::new();
+    write_dot(mapping, project_root, opts, &mut out).expect("writing to a Vec cannot fail");
+    String::from_utf8(out).expect("DOT output is built from strings")
+}
+
+/// Streaming form of [`write_dot_left_right`], writing each line to `out`
+/// as it is produced instead of building the whole graph in memory.
+pub fn write_dot(
+    mapping: &IncludeMapping,
+    project_root: &Path,
+    opts: &DotOptions,
+    out: &mut impl io::Write,
+) -> io::Result<()> {
+    fn esc(s: &str) -> String {
+        s.replace('\\', "\\\\").replace('"', "\\\"")
+    }
+    let classify = |p: &Path| -> (&str, &str) {
+        let ext = p.extension().and_then(|e| e.to_str());
+        if let Some(style) = ext.and_then(|e| opts.node_styles.get(&e.to_ascii_lowercase())) {
+            return (&style.shape, &style.color);
+        }
+        default_style(mapping, p)
+    };
+
+    // Collect sets
+    let mut includees: HashSet<PathBuf> = HashSet::new();
+    let mut includers: HashSet<PathBuf> = HashSet::new();
+    for (inc, who) in &mapping.inner {
+        includees.insert(inc.clone());
+        includers.extend(who.iter().cloned());
+    }
+
+    out.write_all(b"digraph Includes {\n")?;
+    out.write_all(b"  rankdir=LR;\n")?;
+    out.write_all(b"  graph [splines=true, concentrate=true];\n")?;
+    out.write_all(b"  node  [fontname=\"Helvetica\", fontsize=10, style=filled];\n")?;
+    out.write_all(b"  edge  [arrowhead=vee];\n")?;
+
+    // Distinct files each node includes, for --label-with-count
+    let mut counts: HashMap<&Path, usize> = HashMap::new();
+    if opts.label_with_count {
+        for includer in mapping.inner.values().flatten() {
+            *counts.entry(includer).or_default() += 1;
+        }
+    }
+
+    let node_line = |indent: &str, n: &Path| -> String {
+        let (shape, fill) = classify(n);
+        let label = esc(&rel(n, project_root));
+        let count = if opts.label_with_count {
+            let n = counts.get(n).copied().unwrap_or(0);
+            format!(", label=\"{} ({})\"", label, n)
+        } else {
+            String::new()
+        };
+        // Raw include text that matched no file gets a dashed red border
+        let broken = if mapping.unresolved.contains(n) {
+            ", style=\"filled,dashed\", color=\"#d93025\""
+        } else {
+            ""
+        };
+        format!(
+            "{}\"{}\" [shape={}, fillcolor=\"{}\"{}{}];",
+            indent, label, shape, fill, count, broken
+        )
+    };
+
+    if opts.cluster_by_dir {
+        // Top-level files and paths outside the root stay unclustered
+        let mut clusters: BTreeMap<String, BTreeSet<&PathBuf>> = BTreeMap::new();
+        for n in includees.iter().chain(&includers) {
+            let dir = top_level_dir(n, project_root).unwrap_or_default();
+            clusters.entry(dir).or_default().insert(n);
+        }
+        for (dir, nodes) in &clusters {
+            if dir.is_empty() {
+                for n in nodes {
+                    writeln!(out, "{}", node_line("  ", n))?;
+                }
+                continue;
+            }
+            writeln!(out, "  subgraph \"cluster_{}\" {{", esc(dir))?;
+            writeln!(out, "    label=\"{}\";", esc(dir))?;
+            for n in nodes {
+                writeln!(out, "{}", node_line("    ", n))?;
+            }
+            out.write_all(b"  }\n")?;
+        }
+    } else {
+        // Left column: includees (includers when drawing dependencies)
+        let (left, right) = if opts.deps_direction {
+            (&includers, &includees)
+        } else {
+            (&includees, &includers)
+        };
+        out.write_all(b"  { rank=source;\n")?;
+        for n in left {
+            writeln!(out, "{}", node_line("    ", n))?;
+        }
+        out.write_all(b"  }\n")?;
+
+        // Right column: includers (includees when drawing dependencies)
+        out.write_all(b"  { rank=sink;\n")?;
+        for n in right {
+            writeln!(out, "{}", node_line("    ", n))?;
+        }
+        out.write_all(b"  }\n")?;
+    }
+
+    // Edges: includee -> includer, or includer -> includee (so left → right)
+    for (includee_path, who) in &mapping.inner {
+        let includee = esc(&rel(includee_path, project_root));
+        for inc_path in who {
+            let inc = esc(&rel(inc_path, project_root));
+            let (from, to) = if opts.deps_direction {
+                (&inc, &includee)
+            } else {
+                (&includee, &inc)
+            };
+            let times = mapping
+                .lines
+                .get(&(includee_path.clone(), inc_path.clone()))
+                .map_or(1, BTreeSet::len);
+            if opts.edge_weights && times > 1 {
+                writeln!(out, "  \"{}\" -> \"{}\" [label=\"{}\"];", from, to, times)?;
+            } else {
+                writeln!(out, "  \"{}\" -> \"{}\";", from, to)?;
+            }
+        }
+    }
+
+    out.write_all(b"}\n")?;
+    Ok(())
+}
+
+/// Every node of the graph in sorted path order, with the synthetic IDs the
+/// Mermaid, PlantUML and GraphML output use: each node's index in that order.
+fn sorted_node_ids(mapping: &IncludeMapping) -> (BTreeSet<&PathBuf>, HashMap<&PathBuf, usize>) {
+    let mut nodes: BTreeSet<&PathBuf> = BTreeSet::new();
+    for (includee, who) in &mapping.inner {
+        nodes.insert(includee);
+        nodes.extend(who);
+    }
+    let ids = nodes
+        .iter()
+        .enumerate()
+        .map(|(i, n)| (*n, i))
+        .collect::<HashMap<_, _>>();
+    (nodes, ids)
+}
+
+/// Render mapping as a Mermaid flowchart with the same includee -> includer
+/// edges as the DOT output. Node IDs are synthetic (`n0`, `n1`, ...) and
+/// assigned in sorted path order; the relative path becomes the label.
+pub fn write_mermaid(mapping: &IncludeMapping, project_root: &Path) -> String {
+    let (nodes, ids) = sorted_node_ids(mapping);
+
+    let mut out = String::new();
+    out.push_str("graph LR\n");
+    for n in &nodes {
+        let label = rel(n, project_root).replace('"', "#quot;");
+        let _ = writeln!(out, "  n{}[\"{}\"]", ids[n], label);
+    }
+
+    let mut edges = mapping
+        .inner
+        .iter()
+        .flat_map(|(includee, who)| who.iter().map(|w| (ids[includee], ids[w])))
+        .collect::<Vec<_>>();
+    edges.sort();
+    for (from, to) in edges {
+        let _ = writeln!(out, "  n{} --> n{}", from, to);
+    }
+    out
+}
+
+/// Render mapping as a PlantUML component diagram with the same
+/// includee -> includer edges as the DOT output. Components get aliases
+/// like [`write_mermaid`]'s IDs; PlantUML has no escape for `"` inside a
+/// quoted name, so labels show it as `'`.
+pub fn write_plantuml(mapping: &IncludeMapping, project_root: &Path) -> String {
+    let (nodes, ids) = sorted_node_ids(mapping);
+
+    let mut out = String::new();
+    out.push_str("@startuml\nleft to right direction\n");
+    for n in &nodes {
+        let label = rel(n, project_root).replace('"', "'");
+        let _ = writeln!(out, "component \"{}\" as n{}", label, ids[n]);
+    }
+
+    let mut edges = mapping
+        .inner
+        .iter()
+        .flat_map(|(includee, who)| who.iter().map(|w| (ids[includee], ids[w])))
+        .collect::<Vec<_>>();
+    edges.sort();
+    for (from, to) in edges {
+        let _ = writeln!(out, "n{} --> n{}", from, to);
+    }
+    out.push_str("@enduml\n");
+    out
+}
+
+/// Render mapping as GraphML with the same includee -> includer edges as the
+/// DOT output. Each node carries a `label` (relative path) and a `kind`
+/// (`source` or `header`); IDs are assigned as in [`write_mermaid`].
+pub fn write_graphml(mapping: &IncludeMapping, project_root: &Path) -> String {
+    fn esc(s: &str) -> String {
+        s.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+            .replace('\'', "&apos;")
+    }
+
+    let (nodes, ids) = sorted_node_ids(mapping);
+
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+    out.push_str("  <key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>\n");
+    out.push_str("  <key id=\"kind\" for=\"node\" attr.name=\"kind\" attr.type=\"string\"/>\n");
+    out.push_str("  <graph id=\"Includes\" edgedefault=\"directed\">\n");
+    for n in &nodes {
+        let kind = if is_source(n) { "source" } else { "header" };
+        let _ = writeln!(out, "    <node id=\"n{}\">", ids[n]);
+        let _ = writeln!(
+            out,
+            "      <data key=\"label\">{}</data>",
+            esc(&rel(n, project_root))
+        );
+        let _ = writeln!(out, "      <data key=\"kind\">{}</data>", kind);
+        out.push_str("    </node>\n");
+    }
+
+    let mut edges = mapping
+        .inner
+        .iter()
+        .flat_map(|(includee, who)| who.iter().map(|w| (ids[includee], ids[w])))
+        .collect::<Vec<_>>();
+    edges.sort();
+    for (from, to) in edges {
+        let _ = writeln!(out, "    <edge source=\"n{}\" target=\"n{}\"/>", from, to);
+    }
+    out.push_str("  </graph>\n");
+    out.push_str("</graphml>\n");
+    out
+}
+
+/// Stream an adjacency matrix as CSV: rows and columns are every node in
+/// sorted path order, and a cell is 1 when the row file includes the
+/// column file. Rows are written one at a time rather than buffered.
+pub fn write_matrix(
+    mapping: &IncludeMapping,
+    project_root: &Path,
+    out: &mut impl io::Write,
+) -> io::Result<()> {
+    fn field(s: &str) -> String {
+        if s.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", s.replace('"', "\"\""))
+        } else {
+            s.to_string()
+        }
+    }
+
+    let deps = crate::graph::dependency_graph(mapping);
+    let labels = deps
+        .keys()
+        .map(|n| field(&rel(n, project_root)))
+        .collect::<Vec<_>>();
+
+    writeln!(out, ",{}", labels.join(","))?;
+    for ((_, includes), label) in deps.iter().zip(&labels) {
+        let cells = deps
+            .keys()
+            .map(|col| if includes.contains(col) { "1" } else { "0" })
+            .collect::<Vec<_>>();
+        writeln!(out, "{},{}", label, cells.join(","))?;
+    }
+    Ok(())
+}
+
+/// Render mapping as a D2 diagram with the same includee -> includer
+/// connections and node colors as the DOT output. Keys are always quoted,
+/// since D2 would otherwise read the `.` in `foo.h` as nesting.
+pub fn write_d2(mapping: &IncludeMapping, project_root: &Path) -> String {
+    fn quote(s: &str) -> String {
+        format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+
+    let (nodes, _) = sorted_node_ids(mapping);
+
+    let mut out = String::new();
+    out.push_str("direction: right\n");
+    for n in &nodes {
+        let (shape, fill) = default_style(mapping, n);
+        let shape = if shape == "ellipse" {
+            "oval"
+        } else {
+            "rectangle"
+        };
+        let dash = if mapping.unresolved.contains(*n) {
+            "; style.stroke-dash: 3"
+        } else {
+            ""
+        };
+        let _ = writeln!(
+            out,
+            "{}: {{shape: {}; style.fill: \"{}\"{}}}",
+            quote(&rel(n, project_root)),
+            shape,
+            fill,
+            dash
+        );
+    }
+
+    let mut edges = mapping
+        .inner
+        .iter()
+        .flat_map(|(includee, who)| {
+            who.iter()
+                .map(move |w| (rel(includee, project_root), rel(w, project_root)))
+        })
+        .collect::<Vec<_>>();
+    edges.sort();
+    for (from, to) in edges {
+        let _ = writeln!(out, "{} -> {}", quote(&from), quote(&to));
+    }
+    out
+}
+
+/// Page for [`write_html`]; `__DATA__` is replaced by the JSON graph.
+const HTML_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>Include graph</title>
+<style>
+  body { font: 14px Helvetica, Arial, sans-serif; margin: 2em; }
+  input { font: inherit; width: 30em; padding: 0.3em; margin-bottom: 1em; }
+  details { margin-left: 1.2em; }
+  summary { cursor: pointer; }
+  .leaf { margin-left: 2.2em; }
+  .count { color: #777; }
+  .cycle { color: #d93025; }
+</style>
+</head>
+<body>
+<h1>Include graph</h1>
+<p>Each header lists the files that include it; expand a file to see what includes it in turn.</p>
+<input id="filter" type="search" placeholder="Filter headers">
+<div id="tree"></div>
+<script id="graph" type="application/json">__DATA__</script>
+<script>
+"use strict";
+const graph = JSON.parse(document.getElementById("graph").textContent);
+
+function label(path) {
+  const who = graph[path] || [];
+  const span = document.createElement("span");
+  span.textContent = path + " ";
+  const count = document.createElement("span");
+  count.className = "count";
+  count.textContent = who.length ? "(" + who.length + ")" : "";
+  span.appendChild(count);
+  return span;
+}
+
+// Children are built on first expand, so large graphs stay cheap
+function node(path, ancestors) {
+  const who = graph[path] || [];
+  if (ancestors.has(path)) {
+    const div = document.createElement("div");
+    div.className = "leaf cycle";
+    div.textContent = path + " (cycle)";
+    return div;
+  }
+  if (!who.length) {
+    const div = document.createElement("div");
+    div.className = "leaf";
+    div.appendChild(label(path));
+    return div;
+  }
+  const details = document.createElement("details");
+  const summary = document.createElement("summary");
+  summary.appendChild(label(path));
+  details.appendChild(summary);
+  details.addEventListener("toggle", () => {
+    if (!details.open || details.dataset.built) return;
+    details.dataset.built = "1";
+    const inner = new Set(ancestors).add(path);
+    for (const includer of who) details.appendChild(node(includer, inner));
+  });
+  return details;
+}
+
+const tree = document.getElementById("tree");
+const roots = Object.keys(graph).map((p) => [p, node(p, new Set())]);
+for (const [, el] of roots) tree.appendChild(el);
+document.getElementById("filter").addEventListener("input", (e) => {
+  const q = e.target.value.toLowerCase();
+  for (const [p, el] of roots) el.style.display = p.toLowerCase().includes(q) ? "" : "none";
+});
+</script>
+</body>
+</html>
+"#;
+
+/// Render mapping as a self-contained HTML page: the [`write_json`] data
+/// embedded in the page plus a small script drawing a collapsible
+/// includee -> includers tree. Nothing is loaded from the network.
+pub fn write_html(mapping: &IncludeMapping, project_root: &Path) -> Result<String> {
+    let data = relative_map(mapping, project_root);
+    // A path containing `</script>` must not end the embedding element
+    let json = serde_json::to_string(&data)
+        .map_err(CrawlError::Serialize)?
+        .replace("</", "<\\/");
+    Ok(HTML_TEMPLATE.replace("__DATA__", &json))
+}